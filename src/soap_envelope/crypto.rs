@@ -1,9 +1,8 @@
 //! Basic utilities for Base64 encoding and cryptographic signing.
 
 use super::binary_sec_token::BinarySecurityTokenBase64;
-use crate::xml::canonicalization::XmlCanonicalizeError;
+use crate::xml::canonicalization::{CanonizedXml, XmlCanonicalizeError, digest::DigestAlgorithm};
 use base64::{DecodeError, Engine};
-use ring::digest::SHA256;
 use ring::error::KeyRejected;
 use ring::rand::SystemRandom;
 use ring::signature;
@@ -18,9 +17,15 @@ fn from_base64(b64: &str) -> Result<Vec<u8>, DecodeError> {
     base64::prelude::BASE64_STANDARD.decode(b64)
 }
 
+/// Returns the digest of canonical XML as base64, the `ds:DigestValue` of a reference
+pub(crate) fn digest_base64(xml: &CanonizedXml, algorithm: DigestAlgorithm) -> String {
+    to_base64(xml.digest(algorithm).as_ref())
+}
+
 /// Returns a SHA-256 hash as base64
+#[cfg(test)]
 pub(crate) fn sha256_base64(input: &[u8]) -> String {
-    to_base64(ring::digest::digest(&SHA256, input).as_ref())
+    to_base64(ring::digest::digest(&ring::digest::SHA256, input).as_ref())
 }
 
 /// Spočítá hash 256 z dat. Poté spočítá podpis pro ten hash.
@@ -1,5 +1,9 @@
 mod binary_sec_token;
 mod crypto;
+#[cfg(feature = "serde")]
+pub mod serde_body;
+#[allow(clippy::module_inception)]
 pub mod soap_envelope;
 mod soap_envelope_parts;
+#[allow(clippy::module_inception)]
 mod tests;
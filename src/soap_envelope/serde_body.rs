@@ -0,0 +1,254 @@
+//! Serialization of message bodies with serde and deserialization of received bodies (feature `serde`).
+
+use super::soap_envelope::SoapEnvelope;
+use crate::tools::is_ncname;
+use crate::xml::{
+    builder::XmlName,
+    canonicalization::{C14nAlgorithm, C14nSettings, XmlCanonicalizeError, XmlCanonizedDoc},
+    dom::Element,
+    escape::escape_attr_value,
+};
+use quick_xml::{
+    DeError, Writer,
+    events::{BytesStart, Event},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use std::borrow::Cow;
+
+/// Namespace of SOAP 1.1 envelopes
+pub const NS_SOAP11_URI: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+
+/// Namespace of SOAP 1.2 envelopes
+pub const NS_SOAP12_URI: &str = "http://www.w3.org/2003/05/soap-envelope";
+
+/// Error of serializing a body or of reading a received envelope
+#[derive(Debug, thiserror::Error)]
+pub enum SoapSerdeError {
+    #[error("'{0}' is not a valid XML local name")]
+    InvalidName(String),
+
+    #[error("'{0}' is not a valid namespace prefix for namespace '{1}'")]
+    InvalidPrefix(String, String),
+
+    #[error("Failed to serialize the body: {0}")]
+    SerializeError(#[from] quick_xml::SeError),
+
+    #[error("The body does not serialize to a single '{0}' element")]
+    BodyNotElement(String),
+
+    #[error("Failed to deserialize the body: {0}")]
+    DeserializeError(#[from] quick_xml::DeError),
+
+    #[error("Failed to read the XML: {0}")]
+    XmlError(#[from] XmlCanonicalizeError),
+
+    #[error("The root element is not a SOAP 1.1 or SOAP 1.2 Envelope")]
+    NotEnvelope,
+
+    #[error("The SOAP envelope has no Body or the Body is empty")]
+    BodyNotFound,
+
+    #[error("Unexpected body element '{found}', expected '{expected}'")]
+    UnexpectedBody { expected: XmlName, found: XmlName },
+
+    #[error("SOAP fault '{code}': {reason}")]
+    Fault { code: String, reason: String },
+}
+
+/// Namespace and name of the root element of a message body: the operation of a request
+/// or the element of a response. The content of the element is (de)serialized by serde (`quick-xml`),
+/// e.g. the fields of a struct are child elements and fields renamed to `@name` are attributes.
+///
+/// The root element declares the namespace. Without a prefix it is the default namespace,
+/// so it applies to the child elements too (`elementFormDefault="qualified"` in the schema).
+/// With a prefix only the root element is in the namespace (`elementFormDefault="unqualified"`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BodyDescriptor {
+    name: XmlName,
+    prefix: Option<String>,
+}
+
+impl BodyDescriptor {
+    /// Describes the root element with the namespace (`""` for none) and local name
+    pub fn new(ns_url: impl Into<String>, root_name: impl Into<String>) -> Self {
+        Self {
+            name: XmlName::new(ns_url, root_name),
+            prefix: None,
+        }
+    }
+
+    /// Writes the root element with the prefix, the child elements are then in no namespace
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Serializes the body into the XML of the root element.
+    /// The result is passed as `body_xml` to a `SoapEnvelope` constructor, e.g.
+    /// `SoapEnvelope::new_signed(descriptor.serialize(&request)?, public_base64, private_base64)`.
+    /// The body has to serialize to the root element alone: a struct, a unit struct or a primitive.
+    /// Enum variants are written as elements named by the variant, `None` as nothing
+    /// and sequences as more root elements, these fail with `BodyNotElement`.
+    pub fn serialize<T: Serialize + ?Sized>(&self, body: &T) -> Result<String, SoapSerdeError> {
+        let (ns_url, local_name) = (self.name.ns_url(), self.name.local_name());
+        if !is_ncname(local_name) {
+            return Err(SoapSerdeError::InvalidName(local_name.to_owned()));
+        }
+        let (qname, declaration) = match &self.prefix {
+            Some(prefix) if !is_ncname(prefix) || ns_url.is_empty() => {
+                return Err(SoapSerdeError::InvalidPrefix(
+                    prefix.clone(),
+                    ns_url.to_owned(),
+                ));
+            }
+            Some(prefix) => (
+                format!("{prefix}:{local_name}"),
+                format!(" xmlns:{prefix}=\"{}\"", escape_attr_value(ns_url)),
+            ),
+            None if ns_url.is_empty() => (local_name.to_owned(), String::new()),
+            None => (
+                local_name.to_owned(),
+                format!(" xmlns=\"{}\"", escape_attr_value(ns_url)),
+            ),
+        };
+
+        let xml = quick_xml::se::to_string_with_root(&qname, body)?;
+        if !is_single_element(&xml, &qname) {
+            return Err(SoapSerdeError::BodyNotElement(qname));
+        }
+        // Deklarace namespace se vlozi hned za nazev korenoveho elementu
+        let name_end = 1 + qname.len();
+        Ok(format!(
+            "{}{declaration}{}",
+            &xml[..name_end],
+            &xml[name_end..]
+        ))
+    }
+
+    /// Deserializes the body of a received SOAP 1.1 or SOAP 1.2 envelope:
+    /// the first element in the `Body`, which has to have the described namespace and name.
+    /// A SOAP fault is returned as the `Fault` error. The signature of the envelope is not verified.
+    pub fn deserialize<T: DeserializeOwned>(
+        &self,
+        envelope_xml: &str,
+    ) -> Result<T, SoapSerdeError> {
+        let doc = XmlCanonizedDoc::parse(envelope_xml, &C14nSettings::default())?;
+        let payload = body_payload(&doc)?;
+        if !payload.is(self.name.ns_url(), self.name.local_name()) {
+            return Err(SoapSerdeError::UnexpectedBody {
+                expected: self.name.clone(),
+                found: XmlName::new(payload.namespace_uri(), payload.local_name()),
+            });
+        }
+        // Podstrom bez namespacu obalky, serde porovnava jen lokalni jmena
+        let xml = payload.canonize(&C14nSettings::new(C14nAlgorithm::Exclusive));
+        let xml = strip_namespace_declarations(xml.as_str())?;
+        Ok(quick_xml::de::from_str(&xml)?)
+    }
+}
+
+impl<'s> SoapEnvelope<'s> {
+    /// Builds a signed SOAP envelope with the body serialized as described by `descriptor`,
+    /// using the provided Base64-encoded certificate and key. See `BodyDescriptor::serialize`.
+    pub fn new_signed_serialized<T: Serialize + ?Sized>(
+        descriptor: &BodyDescriptor,
+        body: &T,
+        public_certif_base64: impl Into<Cow<'s, str>>,
+        private_key_base64: impl Into<Cow<'s, str>>,
+    ) -> Result<Self, SoapSerdeError> {
+        Ok(Self::new_signed(
+            descriptor.serialize(body)?,
+            public_certif_base64,
+            private_key_base64,
+        ))
+    }
+}
+
+/// Returns `true` if the XML written by `quick-xml` is one element named `qname` and nothing else
+fn is_single_element(xml: &str, qname: &str) -> bool {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let closed = match reader.read_event() {
+        Ok(Event::Start(start)) if start.name().as_ref() == qname.as_bytes() => {
+            reader.read_to_end(start.name()).is_ok()
+        }
+        Ok(Event::Empty(empty)) => empty.name().as_ref() == qname.as_bytes(),
+        _ => false,
+    };
+    closed && reader.buffer_position() == xml.len() as u64
+}
+
+/// Removes the namespace declarations (`xmlns` and `xmlns:*` attributes) from the XML of a body.
+/// `quick-xml` reads them as attributes named `@xmlns`, which a body with
+/// `#[serde(deny_unknown_fields)]` rejects. Prefixes stay, names are matched by the local name.
+fn strip_namespace_declarations(xml: &str) -> Result<String, DeError> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut writer = Writer::new(Vec::new());
+    loop {
+        let event = match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(start) => Event::Start(without_namespace_declarations(&start)?),
+            Event::Empty(empty) => Event::Empty(without_namespace_declarations(&empty)?),
+            event => event,
+        };
+        // Zapis do Vec nemuze selhat
+        let _ = writer.write_event(event);
+    }
+    // Udalosti se kopiruji beze zmeny, vystup je tedy UTF-8 jako vstup
+    Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+}
+
+/// Returns the start tag without its namespace declarations
+fn without_namespace_declarations(start: &BytesStart) -> Result<BytesStart<'static>, DeError> {
+    let mut stripped = start.to_owned();
+    stripped.clear_attributes();
+    for attr in start.attributes() {
+        let attr = attr?;
+        if attr.key.as_namespace_binding().is_none() {
+            stripped.push_attribute(attr);
+        }
+    }
+    Ok(stripped)
+}
+
+/// Returns the first element in the `Body` of the envelope, or the `Fault` error if it is a SOAP fault
+fn body_payload(doc: &XmlCanonizedDoc) -> Result<Element<'_>, SoapSerdeError> {
+    let envelope = doc.root_element();
+    let soap_ns = envelope.namespace_uri();
+    if envelope.local_name() != "Envelope" || !matches!(soap_ns, NS_SOAP11_URI | NS_SOAP12_URI) {
+        return Err(SoapSerdeError::NotEnvelope);
+    }
+    let payload = envelope
+        .first_child(soap_ns, "Body")
+        .and_then(|body| body.child_elements().next())
+        .ok_or(SoapSerdeError::BodyNotFound)?;
+    match payload.is(soap_ns, "Fault") {
+        true => Err(fault_error(payload)),
+        false => Ok(payload),
+    }
+}
+
+/// Reads the code and the reason of a SOAP fault.
+/// SOAP 1.1 has them in the unqualified `faultcode` and `faultstring`,
+/// SOAP 1.2 in `Code/Value` and `Reason/Text` in the envelope namespace.
+fn fault_error(fault: Element) -> SoapSerdeError {
+    let soap_ns = fault.namespace_uri();
+    let (code, reason) = match soap_ns {
+        NS_SOAP11_URI => (
+            fault.first_child("", "faultcode"),
+            fault.first_child("", "faultstring"),
+        ),
+        _ => (
+            fault
+                .first_child(soap_ns, "Code")
+                .and_then(|c| c.first_child(soap_ns, "Value")),
+            fault
+                .first_child(soap_ns, "Reason")
+                .and_then(|r| r.first_child(soap_ns, "Text")),
+        ),
+    };
+    let text = |e: Option<Element>| e.map(|e| e.text().trim().to_owned()).unwrap_or_default();
+    SoapSerdeError::Fault {
+        code: text(code),
+        reason: text(reason),
+    }
+}
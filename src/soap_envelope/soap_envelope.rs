@@ -1,9 +1,12 @@
 use super::{
     binary_sec_token::BinarySecurityTokenBase64,
-    crypto::{XmlSignError, sha256_and_sign_with_pfx, sha256_base64, to_base64},
+    crypto::{XmlSignError, digest_base64, sha256_and_sign_with_pfx, to_base64},
     soap_envelope_parts::{SoapSecurity, Timestamp},
 };
-use crate::xml::canonicalization::{CanonizedXml, XmlCanonicalizeError};
+use crate::xml::canonicalization::{
+    C14nAlgorithm, C14nSettings, CanonizedXml, InclusivePrefixList, XmlCanonicalizeError,
+    XmlCanonizedDoc, digest::DigestAlgorithm, qname_detection::QNameDetection,
+};
 use std::borrow::Cow;
 
 /// The main structure of this library: a SOAP message envelope.
@@ -12,11 +15,17 @@ pub struct SoapEnvelope<'s> {
     /// Optional validity period (from–to, in UTC).
     timestamp: Option<Timestamp>,
 
-    /// The raw XML body of the message.
+    /// The raw XML body of the message. `ElementBuilder::to_xml_string` builds it with escaped content.
     body_xml: Cow<'s, str>,
 
     /// The chosen security variant for this message.
     security: SoapSecurity<'s>,
+
+    /// `InclusiveNamespaces PrefixList` of the exclusive canonicalization of signed parts.
+    inclusive_prefixes: InclusivePrefixList,
+
+    /// Detection of prefixes used in QName-valued content of the body, added to `inclusive_prefixes`.
+    qname_detection: QNameDetection,
 }
 
 impl<'s> SoapEnvelope<'s> {
@@ -35,6 +44,8 @@ impl<'s> SoapEnvelope<'s> {
                 private_base64: private_key_base64.into(),
             },
             timestamp: Some(Timestamp::new_valid_for_minutes(minutes_valid)),
+            inclusive_prefixes: InclusivePrefixList::default(),
+            qname_detection: QNameDetection::Off,
         }
     }
 
@@ -52,6 +63,8 @@ impl<'s> SoapEnvelope<'s> {
                 private_base64: private_key_base64.into(),
             },
             timestamp: None,
+            inclusive_prefixes: InclusivePrefixList::default(),
+            qname_detection: QNameDetection::Off,
         }
     }
 
@@ -61,6 +74,8 @@ impl<'s> SoapEnvelope<'s> {
             body_xml: body_xml.into(),
             security: SoapSecurity::None,
             timestamp: None,
+            inclusive_prefixes: InclusivePrefixList::default(),
+            qname_detection: QNameDetection::Off,
         }
     }
 
@@ -73,16 +88,39 @@ impl<'s> SoapEnvelope<'s> {
             body_xml: body_xml.into(),
             security: SoapSecurity::None,
             timestamp: Some(Timestamp::new_valid_for_minutes(minutes_valid)),
+            inclusive_prefixes: InclusivePrefixList::default(),
+            qname_detection: QNameDetection::Off,
         }
     }
 
+    /// Sets the `InclusiveNamespaces PrefixList` used to canonicalize the signed parts
+    /// (the body and the timestamp). The namespaces with these prefixes are rendered
+    /// even if they are not visibly used, e.g. when they are referenced by QName-valued content.
+    /// The list is sent in each `ds:Transform` of the signature.
+    pub fn with_inclusive_prefixes(mut self, prefix_list: InclusivePrefixList) -> Self {
+        self.inclusive_prefixes = prefix_list;
+        self
+    }
+
+    /// Sets the detection of prefixes used in QName-valued content of the body
+    /// (e.g. `xsi:type="tns:OrderType"`). The detected prefixes are added to the `InclusiveNamespaces PrefixList`,
+    /// so the signature stays valid when an intermediary moves or strips unused namespace declarations.
+    pub fn with_qname_detection(mut self, qname_detection: QNameDetection) -> Self {
+        self.qname_detection = qname_detection;
+        self
+    }
+
     /// Returns SOAP envelope XML as String
     /// This operation may fail if it includes XML canonicalization and signing
     pub fn get_final_xml(&self) -> Result<String, XmlSignError> {
-        let timestamp = self.get_timestamp()?;
-        let fullbody = self.get_fullbody()?;
-        let signed_info_xml =
-            self.get_ds_signed_info(timestamp.as_ref(), &sha256_base64(fullbody.as_bytes()))?;
+        let prefix_list = self.get_inclusive_prefixes()?;
+        let timestamp = self.get_timestamp(&prefix_list)?;
+        let fullbody = self.get_fullbody(&prefix_list)?;
+        let signed_info_xml = self.get_ds_signed_info(
+            timestamp.as_ref(),
+            &digest_base64(&fullbody, DIGEST_ALGORITHM),
+            &prefix_list,
+        )?;
 
         let timestamp_elem_xml = match timestamp {
             Some(timestamp) => format!("{timestamp}"),
@@ -102,7 +140,7 @@ impl<'s> SoapEnvelope<'s> {
                 let (doc_sign, binary_sec_token_base64) = self.get_doc_signature(
                     &signed_info_xml,
                     public_base64.to_string(),
-                    &private_base64,
+                    private_base64,
                 )?;
 
                 format!(
@@ -129,36 +167,55 @@ impl<'s> SoapEnvelope<'s> {
 
         Ok(format!(
             r##"
-    <soapenv:Envelope xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/"
-                  xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd"
-                  xmlns:ds="http://www.w3.org/2000/09/xmldsig#"
-                  xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd">
+    <soapenv:Envelope {ENVELOPE_XMLNS}>
     <soapenv:Header>{wsse_security_elem_xml}</soapenv:Header>{fullbody}</soapenv:Envelope>
     "##
         ))
     }
 
-    /// Builds the complete `<soapenv:Body>` element of the SOAP message as canonical XML.
-    fn get_fullbody(&self) -> Result<CanonizedXml, XmlCanonicalizeError> {
-        let fullbody = format!(
+    /// Returns the `InclusiveNamespaces PrefixList` of the signed parts:
+    /// the list set by the user together with the prefixes detected in the body.
+    fn get_inclusive_prefixes(&self) -> Result<InclusivePrefixList, XmlCanonicalizeError> {
+        if self.qname_detection == QNameDetection::Off {
+            return Ok(self.inclusive_prefixes.clone());
+        }
+        let settings = C14nSettings::new(C14nAlgorithm::Exclusive)
+            .with_inclusive_prefixes(self.inclusive_prefixes.clone())
+            .with_qname_detection(self.qname_detection.clone());
+        XmlCanonizedDoc::parse(&self.get_fullbody_xml(), &settings)?
+            .inclusive_prefixes_for("", &settings)
+    }
+
+    /// Builds the complete `<soapenv:Body>` element of the SOAP message.
+    fn get_fullbody_xml(&self) -> String {
+        format!(
             r#"
-        <soapenv:Body xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" 
-            xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd" 
-            wsu:Id="{BODY_ID}">{}</soapenv:Body>
+        <soapenv:Body {ENVELOPE_XMLNS} wsu:Id="{BODY_ID}">{}</soapenv:Body>
         "#,
             self.body_xml
-        );
-        let body_canoni = CanonizedXml::new(&fullbody)?;
-        Ok(body_canoni)
+        )
+    }
+
+    /// Builds the complete `<soapenv:Body>` element of the SOAP message as canonical XML.
+    fn get_fullbody(
+        &self,
+        prefix_list: &InclusivePrefixList,
+    ) -> Result<CanonizedXml, XmlCanonicalizeError> {
+        // Cast deklaruje vsechny namespacy obalky, aby se prefixy z PrefixList
+        // vyhodnotily stejne jako ve vysledne zprave
+        CanonizedXml::new_exclusive_with_prefix_list(&self.get_fullbody_xml(), prefix_list)
     }
 
     /// Builds the complete `<wsu:Timestamp>` element of the SOAP message as canonical XML.
-    fn get_timestamp(&self) -> Result<Option<CanonizedXml>, XmlCanonicalizeError> {
+    fn get_timestamp(
+        &self,
+        prefix_list: &InclusivePrefixList,
+    ) -> Result<Option<CanonizedXml>, XmlCanonicalizeError> {
         match &self.timestamp {
             Some(timestamp) => {
                 let timestamp_xml = format!(
                     r#"
-                        <wsu:Timestamp xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd" wsu:Id="{TIMESTAMP_ID}">
+                        <wsu:Timestamp {ENVELOPE_XMLNS} wsu:Id="{TIMESTAMP_ID}">
                             <wsu:Created>{}</wsu:Created>
                             <wsu:Expires>{}</wsu:Expires>
                         </wsu:Timestamp>
@@ -167,7 +224,8 @@ impl<'s> SoapEnvelope<'s> {
                     timestamp.expires_str(),
                 );
 
-                let timestamp_canoni = CanonizedXml::new(&timestamp_xml)?;
+                let timestamp_canoni =
+                    CanonizedXml::new_exclusive_with_prefix_list(&timestamp_xml, prefix_list)?;
                 Ok(Some(timestamp_canoni))
             }
             None => Ok(None),
@@ -179,19 +237,20 @@ impl<'s> SoapEnvelope<'s> {
         &self,
         timestamp: Option<&CanonizedXml>,
         body_hash: &str,
+        prefix_list: &InclusivePrefixList,
     ) -> Result<CanonizedXml, XmlCanonicalizeError> {
+        let transform_xml = self.get_ds_transform(prefix_list);
+        let digest_method = DIGEST_ALGORITHM.uri();
         let timestamp_reference_xml = match timestamp {
             Some(timestamp) => format!(
                 r##"
              <ds:Reference URI="#{TIMESTAMP_ID}">
-                        <ds:Transforms>
-                            <ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#" />
-                        </ds:Transforms>
-                        <ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256" />
+                        <ds:Transforms>{transform_xml}</ds:Transforms>
+                        <ds:DigestMethod Algorithm="{digest_method}" />
                         <ds:DigestValue>{timestamp_hash}</ds:DigestValue>
                     </ds:Reference>
             "##,
-                timestamp_hash = sha256_base64(timestamp.as_bytes())
+                timestamp_hash = digest_base64(timestamp, DIGEST_ALGORITHM)
             ),
             None => String::new(),
         };
@@ -203,10 +262,8 @@ impl<'s> SoapEnvelope<'s> {
                     <ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/>
                    {timestamp_reference_xml}
         <ds:Reference URI="#{BODY_ID}">
-               <ds:Transforms>
-                   <ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/>
-               </ds:Transforms>
-               <ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/>
+               <ds:Transforms>{transform_xml}</ds:Transforms>
+               <ds:DigestMethod Algorithm="{digest_method}"/>
                <ds:DigestValue>{body_hash}</ds:DigestValue>
            </ds:Reference>
         </ds:SignedInfo>
@@ -214,6 +271,19 @@ impl<'s> SoapEnvelope<'s> {
         ))
     }
 
+    /// Builds the `<ds:Transform>` element of the references to the signed parts,
+    /// including the `<ec:InclusiveNamespaces>` parameter if there is a prefix list.
+    fn get_ds_transform(&self, prefix_list: &InclusivePrefixList) -> String {
+        let algorithm = C14nAlgorithm::Exclusive.uri();
+        if prefix_list.is_empty() {
+            format!(r#"<ds:Transform Algorithm="{algorithm}"/>"#)
+        } else {
+            format!(
+                r#"<ds:Transform Algorithm="{algorithm}"><ec:InclusiveNamespaces xmlns:ec="{algorithm}" PrefixList="{prefix_list}"/></ds:Transform>"#
+            )
+        }
+    }
+
     /// Generates the full digital signature and security token from the given `<SignedInfo>` XML element.
     /// Uses the provided Base64-encoded certificate and private key to create the signature.
     fn get_doc_signature(
@@ -232,6 +302,15 @@ impl<'s> SoapEnvelope<'s> {
     }
 }
 
+/// Namespaces declared on the `<soapenv:Envelope>` XML element.
+const ENVELOPE_XMLNS: &str = r#"xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/"
+                  xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd"
+                  xmlns:ds="http://www.w3.org/2000/09/xmldsig#"
+                  xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd""#;
+
+/// `ds:DigestMethod` of the references to the signed parts.
+const DIGEST_ALGORITHM: DigestAlgorithm = DigestAlgorithm::Sha256;
+
 /// `wsu:Id` for the `<Timestamp>` XML element.
 const TIMESTAMP_ID: &str = "Timsta";
 
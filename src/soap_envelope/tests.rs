@@ -4,6 +4,12 @@ mod tests {
         crypto::{sha256_and_sign_with_pfx, sha256_base64, to_base64},
         soap_envelope::SoapEnvelope,
     };
+    use crate::xml::{
+        builder::ElementBuilder,
+        canonicalization::{
+            C14nSettings, CanonizedXml, InclusivePrefixList, qname_detection::QNameDetection,
+        },
+    };
 
     #[test]
     fn test_sha_base64() {
@@ -13,15 +19,352 @@ mod tests {
         assert_eq!(hash, expected_hash)
     }
 
+    /// Returns the part of `xml` that starts with `from` and ends with `to` (inclusive)
+    fn cut<'a>(xml: &'a str, from: &str, to: &str) -> &'a str {
+        let start = xml.find(from).unwrap();
+        let end = start + xml[start..].find(to).unwrap() + to.len();
+        &xml[start..end]
+    }
+
+    /// Checks that the envelope is signed by the test key over its own `ds:SignedInfo`
+    fn assert_signature(xml: &str) {
+        let signed_info = cut(xml, "<ds:SignedInfo", "</ds:SignedInfo>");
+        let signat =
+            sha256_and_sign_with_pfx(CERT_PUBLIC.to_owned(), CERT_PRIV, signed_info.as_bytes())
+                .unwrap();
+        let signature_value = format!(
+            "<ds:SignatureValue>{}</ds:SignatureValue>",
+            to_base64(&signat.signature)
+        );
+        assert!(xml.contains(&signature_value));
+        assert!(xml.contains(&signat.binary_security_token.to_string()));
+    }
+
+    /// Checks the digest of a reference the way a receiver does: by canonicalizing
+    /// the element with the given `wsu:Id` inside the final message
+    fn assert_reference_digest(xml: &str, id: &str, settings: &C14nSettings) {
+        let part = CanonizedXml::new_subtree_by_id(xml, id, settings).unwrap();
+        assert!(xml.contains(&format!(r##"<ds:Reference URI="#{id}">"##)));
+        assert!(xml.contains(&format!(
+            "<ds:DigestValue>{}</ds:DigestValue>",
+            sha256_base64(part.as_bytes())
+        )));
+    }
+
     #[test]
     fn test_signed_soapenv_with_timestamp() {
-        todo!();
+        let envelope = SoapEnvelope::new_signed_with_timestamp(
+            5,
+            r#"<m:Ping xmlns:m="http://m.m">1</m:Ping>"#,
+            CERT_PUBLIC,
+            CERT_PRIV,
+        );
+        let xml = envelope.get_final_xml().unwrap();
+
+        let timestamp = cut(&xml, "<wsu:Timestamp", "</wsu:Timestamp>");
+        assert!(timestamp.contains("<wsu:Created>"));
+        assert!(timestamp.contains("<wsu:Expires>"));
+        assert!(xml.contains(&format!(
+            "<ds:DigestValue>{}</ds:DigestValue>",
+            sha256_base64(timestamp.as_bytes())
+        )));
+        assert_reference_digest(&xml, "Timsta", &C14nSettings::default());
+        assert_reference_digest(&xml, "Msgbody", &C14nSettings::default());
+        assert_signature(&xml);
     }
 
     #[test]
     fn test_signed_soapenv() {
-        // SoapEnvelope::
-        todo!();
+        let envelope = SoapEnvelope::new_signed(
+            r#"<m:Ping xmlns:m="http://m.m">1</m:Ping>"#,
+            CERT_PUBLIC,
+            CERT_PRIV,
+        );
+        let xml = envelope.get_final_xml().unwrap();
+
+        let body = r#"<soapenv:Body xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd" wsu:Id="Msgbody"><m:Ping xmlns:m="http://m.m">1</m:Ping></soapenv:Body>"#;
+        assert!(xml.contains(body));
+        assert!(xml.contains(&format!(
+            "<ds:DigestValue>{}</ds:DigestValue>",
+            sha256_base64(body.as_bytes())
+        )));
+        assert!(!xml.contains("wsu:Timestamp"));
+        assert_signature(&xml);
+    }
+
+    #[test]
+    fn test_signed_soapenv_with_inclusive_prefixes() {
+        let envelope = SoapEnvelope::new_signed(
+            r#"<m:Ping xmlns:m="http://m.m">1</m:Ping>"#,
+            CERT_PUBLIC,
+            CERT_PRIV,
+        )
+        .with_inclusive_prefixes(InclusivePrefixList::parse("wsse"));
+        let xml = envelope.get_final_xml().unwrap();
+
+        let body = r#"<soapenv:Body xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd" wsu:Id="Msgbody"><m:Ping xmlns:m="http://m.m">1</m:Ping></soapenv:Body>"#;
+        assert!(xml.contains(body));
+        assert!(xml.contains(&format!(
+            "<ds:DigestValue>{}</ds:DigestValue>",
+            sha256_base64(body.as_bytes())
+        )));
+        assert!(xml.contains(r#"<ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"><ec:InclusiveNamespaces xmlns:ec="http://www.w3.org/2001/10/xml-exc-c14n#" PrefixList="wsse"></ec:InclusiveNamespaces></ds:Transform>"#));
+        assert_reference_digest(
+            &xml,
+            "Msgbody",
+            &C14nSettings::default().with_inclusive_prefixes(InclusivePrefixList::parse("wsse")),
+        );
+        assert_signature(&xml);
+    }
+
+    #[test]
+    fn test_signed_soapenv_with_qname_detection() {
+        let envelope = SoapEnvelope::new_signed(
+            r#"<m:Order xmlns:m="http://m.m" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:t="http://t.t"><m:Item xsi:type="t:Book">1</m:Item></m:Order>"#,
+            CERT_PUBLIC,
+            CERT_PRIV,
+        )
+        .with_qname_detection(QNameDetection::xsi_type());
+        let xml = envelope.get_final_xml().unwrap();
+
+        // Prefix t neni viditelne pouzity, deklaraci drzi jen PrefixList
+        assert!(xml.contains(r#"<m:Order xmlns:m="http://m.m" xmlns:t="http://t.t"><m:Item xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="t:Book">1</m:Item></m:Order>"#));
+        assert!(xml.contains(r#"PrefixList="t""#));
+        assert_reference_digest(
+            &xml,
+            "Msgbody",
+            &C14nSettings::default().with_inclusive_prefixes(InclusivePrefixList::parse("t")),
+        );
+        assert_signature(&xml);
+    }
+
+    #[test]
+    fn test_signed_soapenv_with_built_body() {
+        let body = ElementBuilder::new(("http://m.m", "Ping"))
+            .with_prefix("m", "http://m.m")
+            .with_attr("note", r#""/><m:Injected/>"#)
+            .with_text("</m:Ping><m:Injected/>")
+            .to_xml_string()
+            .unwrap();
+        let envelope = SoapEnvelope::new_signed(body, CERT_PUBLIC, CERT_PRIV);
+        let xml = envelope.get_final_xml().unwrap();
+
+        assert!(xml.contains(r#"<m:Ping xmlns:m="http://m.m" note="&quot;/>&lt;m:Injected/>">&lt;/m:Ping&gt;&lt;m:Injected/&gt;</m:Ping>"#));
+        assert!(!xml.contains("<m:Injected"));
+        assert_reference_digest(&xml, "Msgbody", &C14nSettings::default());
+        assert_signature(&xml);
+    }
+
+    #[cfg(feature = "serde")]
+    mod serde_body {
+        use super::{CERT_PRIV, CERT_PUBLIC, assert_reference_digest, assert_signature};
+        use crate::soap_envelope::{
+            serde_body::{BodyDescriptor, SoapSerdeError},
+            soap_envelope::SoapEnvelope,
+        };
+        use crate::xml::{builder::XmlName, canonicalization::C14nSettings};
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Order {
+            #[serde(rename = "@id")]
+            id: u32,
+            #[serde(rename = "Customer")]
+            customer: String,
+            #[serde(rename = "Item")]
+            items: Vec<Item>,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Item {
+            #[serde(rename = "@count")]
+            count: u32,
+            #[serde(rename = "$text")]
+            name: String,
+        }
+
+        /// Unknown attributes and elements are rejected, namespace declarations too
+        #[derive(Debug, PartialEq, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct StrictOrder {
+            #[serde(rename = "@id")]
+            id: u32,
+            #[serde(rename = "Customer")]
+            customer: String,
+        }
+
+        #[derive(Serialize)]
+        enum Choice {
+            Cash,
+            Card(u32),
+        }
+
+        fn order() -> Order {
+            Order {
+                id: 7,
+                customer: "Smith & <Sons>".to_owned(),
+                items: vec![
+                    Item {
+                        count: 2,
+                        name: "Book".to_owned(),
+                    },
+                    Item {
+                        count: 1,
+                        name: "Pen".to_owned(),
+                    },
+                ],
+            }
+        }
+
+        #[test]
+        fn test_serialize_body() {
+            let descriptor = BodyDescriptor::new("http://m.m", "Order");
+            assert_eq!(
+                descriptor.serialize(&order()).unwrap(),
+                r#"<Order xmlns="http://m.m" id="7"><Customer>Smith &amp; &lt;Sons&gt;</Customer><Item count="2">Book</Item><Item count="1">Pen</Item></Order>"#
+            );
+            assert_eq!(
+                descriptor.with_prefix("m").serialize(&()).unwrap(),
+                r#"<m:Order xmlns:m="http://m.m"/>"#
+            );
+            assert!(matches!(
+                BodyDescriptor::new("http://m.m", "m:Order").serialize(&()),
+                Err(SoapSerdeError::InvalidName(_))
+            ));
+            assert!(matches!(
+                BodyDescriptor::new("", "Order")
+                    .with_prefix("m")
+                    .serialize(&()),
+                Err(SoapSerdeError::InvalidPrefix(..))
+            ));
+        }
+
+        /// Bodies which are not written as the root element alone are rejected
+        #[test]
+        fn test_serialize_body_shapes() {
+            let descriptor = BodyDescriptor::new("http://m.m", "B");
+            assert_eq!(
+                descriptor.serialize(&Some(3)).unwrap(),
+                r#"<B xmlns="http://m.m">3</B>"#
+            );
+            assert_eq!(
+                descriptor.serialize("a<b").unwrap(),
+                r#"<B xmlns="http://m.m">a&lt;b</B>"#
+            );
+
+            let not_element = |result: Result<String, SoapSerdeError>| {
+                assert!(matches!(result, Err(SoapSerdeError::BodyNotElement(name)) if name == "B"));
+            };
+            not_element(descriptor.serialize(&Choice::Cash));
+            not_element(descriptor.serialize(&Choice::Card(3)));
+            not_element(descriptor.serialize(&None::<u32>));
+            not_element(descriptor.serialize(&vec![1, 2]));
+            not_element(descriptor.serialize(&vec![order(), order()]));
+        }
+
+        /// The body sent in a signed envelope is deserialized from the final message
+        #[test]
+        fn test_signed_envelope_round_trip() {
+            let descriptor = BodyDescriptor::new("http://m.m", "Order");
+            let envelope =
+                SoapEnvelope::new_signed_serialized(&descriptor, &order(), CERT_PUBLIC, CERT_PRIV)
+                    .unwrap();
+            let xml = envelope.get_final_xml().unwrap();
+            assert_reference_digest(&xml, "Msgbody", &C14nSettings::default());
+            assert_signature(&xml);
+
+            assert_eq!(descriptor.deserialize::<Order>(&xml).unwrap(), order());
+            match BodyDescriptor::new("http://m.m", "Invoice").deserialize::<Order>(&xml) {
+                Err(SoapSerdeError::UnexpectedBody { expected, found }) => {
+                    assert_eq!(expected, XmlName::new("http://m.m", "Invoice"));
+                    assert_eq!(found, XmlName::new("http://m.m", "Order"));
+                }
+                other => panic!("unexpected result {other:?}"),
+            }
+        }
+
+        #[test]
+        fn test_deserialize_response() {
+            let descriptor = BodyDescriptor::new("http://m.m", "Order").with_prefix("m");
+            // Prefixy odpovedi se lisi od pozadavku, rozhoduje namespace
+            let xml = r#"<e:Envelope xmlns:e="http://www.w3.org/2003/05/soap-envelope" xmlns:x="http://m.m">
+                <e:Header/>
+                <e:Body>
+                    <x:Order id="7">
+                        <Customer>Smith &amp; &lt;Sons&gt;</Customer>
+                        <Item count="2">Book</Item>
+                        <Item count="1"><![CDATA[Pen]]></Item>
+                    </x:Order>
+                </e:Body>
+            </e:Envelope>"#;
+            assert_eq!(descriptor.deserialize::<Order>(xml).unwrap(), order());
+
+            assert!(matches!(
+                descriptor.deserialize::<Order>(r#"<Envelope><Body/></Envelope>"#),
+                Err(SoapSerdeError::NotEnvelope)
+            ));
+            assert!(matches!(
+                descriptor.deserialize::<Order>(
+                    r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body> </s:Body></s:Envelope>"#
+                ),
+                Err(SoapSerdeError::BodyNotFound)
+            ));
+            assert!(matches!(
+                descriptor.deserialize::<Order>("<s:Envelope"),
+                Err(SoapSerdeError::XmlError(_))
+            ));
+        }
+
+        /// Only the namespaces used by the body are declared on it, not those of the envelope
+        #[test]
+        fn test_deserialize_strict_body() {
+            let xml = r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" xmlns:wsu="http://wsu.wsu" xmlns:x="http://m.m">
+                <s:Body wsu:Id="Msgbody"><x:Order id="7"><Customer>Smith</Customer></x:Order></s:Body>
+            </s:Envelope>"#;
+            let descriptor = BodyDescriptor::new("http://m.m", "Order");
+            let expected = StrictOrder {
+                id: 7,
+                customer: "Smith".to_owned(),
+            };
+            assert_eq!(
+                descriptor.deserialize::<StrictOrder>(xml).unwrap(),
+                expected
+            );
+            // Vlastni deklarace namespace tela se take vynecha, neznamy atribut ne
+            let own_ns = xml
+                .replace("<x:Order id=", r#"<Order xmlns="http://m.m" id="#)
+                .replace("</x:Order>", "</Order>");
+            assert_eq!(
+                descriptor.deserialize::<StrictOrder>(&own_ns).unwrap(),
+                expected
+            );
+            assert!(matches!(
+                descriptor
+                    .deserialize::<StrictOrder>(&xml.replace("id=\"7\"", "id=\"7\" extra=\"1\"")),
+                Err(SoapSerdeError::DeserializeError(_))
+            ));
+        }
+
+        #[test]
+        fn test_deserialize_fault() {
+            let soap11 = r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><s:Fault>
+                <faultcode>s:Client</faultcode><faultstring> Unknown order </faultstring>
+            </s:Fault></s:Body></s:Envelope>"#;
+            let soap12 = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"><env:Body><env:Fault>
+                <env:Code><env:Value>env:Sender</env:Value></env:Code>
+                <env:Reason><env:Text xml:lang="en">Unknown order</env:Text></env:Reason>
+            </env:Fault></env:Body></env:Envelope>"#;
+            let descriptor = BodyDescriptor::new("http://m.m", "Order");
+            for (xml, expected_code) in [(soap11, "s:Client"), (soap12, "env:Sender")] {
+                match descriptor.deserialize::<Order>(xml) {
+                    Err(SoapSerdeError::Fault { code, reason }) => {
+                        assert_eq!(code, expected_code);
+                        assert_eq!(reason, "Unknown order");
+                    }
+                    other => panic!("unexpected result {other:?}"),
+                }
+            }
+        }
     }
 
     const CERT_PUBLIC: &str = "MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAz8q1sR3OERJXHkX0dJJgiQUJK07G2/48MeIBXVeHd49jEmf7SAP4/S00EGspMhTFQDlZ2QkhtiBBSmQMjAcGm4vwz+uxR05+jeMhmcTxO5IVf+gnr1spd0udLNq30hwfJk2qlTOO+Oc0LZQA8eO6pvlZl9rGkFC2HPhCTsNPglWFRnErMn7YkRF7Rptk5ra4/+1RxuUjiGkfMNuDhxV00Gf8Y5BX4eoVacAw6pInfjZsQO+vbXO5Z+7kGWDfC06PcRWfWYOMmnhIs6tQDqIGM/j85NjrTGXZvvK9PNQGK1cz/PdyoTn5lFGT8ZW0/mrBHNKC0jgI5RkaxwxzKUKbbQIDAQAB";
@@ -1,28 +1,21 @@
-use std::cmp::Ordering;
-
 use tinystr::TinyStr16;
 
-/// Compares two strs in case-insensitive mode.
-/// Doesn't create any clones.
-pub(crate) trait StrCmpIgnoreCase {
-    fn cmp_ignore_case(&self, other: &Self) -> Ordering;
+pub(crate) fn str_to_tinystr16(s: &str) -> Result<TinyStr16, tinystr::ParseError> {
+    TinyStr16::try_from_utf8(s.as_bytes())
 }
 
-impl StrCmpIgnoreCase for str {
-    fn cmp_ignore_case(&self, other: &Self) -> Ordering {
-        // Iterativní porovnání znaků
-        for (c1, c2) in self.chars().zip(other.chars()) {
-            let cmp = c1.to_ascii_lowercase().cmp(&c2.to_ascii_lowercase());
-            if cmp != Ordering::Equal {
-                return cmp; // Pokud najdeme rozdíl, vrátíme výsledek
-            }
-        }
-
-        // Porovnáme délky, pokud všechny znaky byly shodné
-        self.len().cmp(&other.len())
+/// Returns `true` if the string is a non-colonized name (a prefix or a local name).
+/// Non-ASCII letters and digits are accepted as a whole, without the exact XML character classes.
+pub(crate) fn is_ncname(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {}
+        _ => return false,
     }
+    chars.all(is_ncname_char)
 }
 
-pub(crate) fn str_to_tinystr16(s: &str) -> Result<TinyStr16, tinystr::ParseError> {
-    TinyStr16::try_from_utf8(s.as_bytes())
+/// Returns `true` if the character may follow the first one of a non-colonized name, see `is_ncname`
+pub(crate) fn is_ncname_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '\u{B7}')
 }
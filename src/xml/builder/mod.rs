@@ -0,0 +1,365 @@
+#[allow(clippy::module_inception)]
+mod tests;
+
+use super::{
+    canonicalization::{C14nAlgorithm, C14nSettings, XmlCanonizedDoc},
+    xml_attr::XmlAttr,
+    xml_elem::{ElemId, XmlElem, XmlElemArena, XmlNode},
+    xmlns::{NsInterner, Xmlns, XmlnsPrefix},
+    xmlns_scope::XmlnsScope,
+};
+use crate::tools::is_ncname;
+use std::{collections::BTreeSet, fmt::Display};
+use xml::{
+    common::TextPosition,
+    namespace::{NS_XML_PREFIX, NS_XML_URI, NS_XMLNS_PREFIX, NS_XMLNS_URI},
+};
+
+/// Name of an element or attribute: the namespace URI (empty for no namespace) and the local name.
+/// The prefix is not part of the name, the builder chooses it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct XmlName {
+    ns_url: String,
+    local_name: String,
+}
+
+impl XmlName {
+    /// Creates a name in the namespace
+    pub fn new(ns_url: impl Into<String>, local_name: impl Into<String>) -> Self {
+        Self {
+            ns_url: ns_url.into(),
+            local_name: local_name.into(),
+        }
+    }
+
+    /// Creates a name in no namespace
+    pub fn local(local_name: impl Into<String>) -> Self {
+        Self::new("", local_name)
+    }
+
+    /// Returns the namespace URI, or an empty string for a name in no namespace
+    pub fn ns_url(&self) -> &str {
+        &self.ns_url
+    }
+
+    /// Returns the local name
+    pub fn local_name(&self) -> &str {
+        &self.local_name
+    }
+}
+
+/// A name in no namespace
+impl From<&str> for XmlName {
+    fn from(local_name: &str) -> Self {
+        Self::local(local_name)
+    }
+}
+
+/// A name given as (namespace URI, local name)
+impl From<(&str, &str)> for XmlName {
+    fn from((ns_url, local_name): (&str, &str)) -> Self {
+        Self::new(ns_url, local_name)
+    }
+}
+
+/// Writes the name in the James Clark notation, `{namespace-uri}local`
+impl Display for XmlName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.ns_url.is_empty() {
+            true => f.write_str(&self.local_name),
+            false => write!(f, "{{{}}}{}", self.ns_url, self.local_name),
+        }
+    }
+}
+
+/// Error of building an XML tree, the content can't be written as well-formed XML
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum XmlBuildError {
+    #[error("'{0}' is not a valid XML local name")]
+    InvalidName(String),
+
+    #[error("'{0}' is not a valid namespace prefix")]
+    InvalidPrefix(String),
+
+    #[error("Namespace '{0}' is reserved for namespace declarations")]
+    ReservedNamespace(String),
+
+    #[error("Attribute '{0}' is set more than once on the element")]
+    DuplicateAttr(XmlName),
+
+    #[error("Character U+{0:04X} is not allowed in XML")]
+    InvalidChar(u32),
+}
+
+/// Builds an XML element with its attributes and content, without writing any markup by hand.
+/// Names are given by namespace URI and local name, the prefixes are allocated when building:
+/// a namespace already bound in scope is reused, otherwise it is declared on the element which uses it,
+/// with the preferred prefix (see `with_prefix`) or a generated one (`ns1`, `ns2`, ...).
+/// Texts and attribute values are escaped, names and characters are checked,
+/// so no content can change the structure of the document.
+///
+/// The result is a `XmlCanonizedDoc`, ready to be canonicalized, or its XML string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementBuilder {
+    name: XmlName,
+    attrs: Vec<(XmlName, String)>,
+    children: Vec<BuilderNode>,
+
+    /// Preferred prefixes of namespaces in the subtree, as (prefix, namespace URI)
+    prefixes: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BuilderNode {
+    Elem(ElementBuilder),
+    Text(String),
+}
+
+impl ElementBuilder {
+    /// Creates an empty element
+    pub fn new(name: impl Into<XmlName>) -> Self {
+        Self {
+            name: name.into(),
+            attrs: Vec::new(),
+            children: Vec::new(),
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Adds an attribute. The value is escaped when written.
+    /// An attribute in a namespace always gets a prefix, the default namespace doesn't apply to attributes.
+    pub fn with_attr(mut self, name: impl Into<XmlName>, value: impl Into<String>) -> Self {
+        self.attrs.push((name.into(), value.into()));
+        self
+    }
+
+    /// Appends a text after the current content. The text is escaped when written.
+    pub fn with_text(mut self, text: impl Into<String>) -> Self {
+        self.children.push(BuilderNode::Text(text.into()));
+        self
+    }
+
+    /// Appends a child element after the current content
+    pub fn with_child(mut self, child: ElementBuilder) -> Self {
+        self.children.push(BuilderNode::Elem(child));
+        self
+    }
+
+    /// Appends child elements after the current content
+    pub fn with_children(mut self, children: impl IntoIterator<Item = ElementBuilder>) -> Self {
+        self.children
+            .extend(children.into_iter().map(BuilderNode::Elem));
+        self
+    }
+
+    /// Sets the preferred prefix of the namespace in the subtree of this element.
+    /// The empty prefix makes the namespace the default one for elements.
+    /// The prefix is used unless it is already bound to another namespace in scope,
+    /// a later preferred prefix of the same namespace takes precedence.
+    pub fn with_prefix(mut self, prefix: impl Into<String>, ns_url: impl Into<String>) -> Self {
+        self.prefixes.push((prefix.into(), ns_url.into()));
+        self
+    }
+
+    /// Builds the document with this element as the root element
+    pub fn build(&self) -> Result<XmlCanonizedDoc, XmlBuildError> {
+        let mut tree = TreeBuilder::default();
+        let root = tree.add_elem(self, None)?;
+        Ok(XmlCanonizedDoc::from_elems(tree.elems, root))
+    }
+
+    /// Builds the document and writes it as XML, without the XML declaration.
+    /// The output is in the canonical form (Canonical XML 1.0), so it has no insignificant whitespace
+    /// and attributes are ordered, and it can be embedded into another document, e.g. as the body of a `SoapEnvelope`.
+    pub fn to_xml_string(&self) -> Result<String, XmlBuildError> {
+        let settings = C14nSettings::new(C14nAlgorithm::Inclusive);
+        Ok(self.build()?.canonize(&settings).to_string())
+    }
+}
+
+/// Builds the elements of a document into an arena and allocates their prefixes
+#[derive(Default)]
+struct TreeBuilder {
+    elems: XmlElemArena,
+    interner: NsInterner,
+
+    /// Namespaces in scope at the element being built
+    known_nss: XmlnsScope,
+
+    /// Preferred prefixes of the element being built and its ancestors, innermost last
+    preferred: Vec<(String, String)>,
+}
+
+impl TreeBuilder {
+    /// Adds the element with its subtree, after the current children of `parent`
+    fn add_elem(
+        &mut self,
+        builder: &ElementBuilder,
+        parent: Option<ElemId>,
+    ) -> Result<ElemId, XmlBuildError> {
+        for (prefix, _) in builder.prefixes.iter() {
+            let reserved = matches!(prefix.as_str(), NS_XML_PREFIX | NS_XMLNS_PREFIX);
+            if reserved || !(prefix.is_empty() || is_ncname(prefix)) {
+                return Err(XmlBuildError::InvalidPrefix(prefix.clone()));
+            }
+        }
+        let preferred_len = self.preferred.len();
+        self.preferred.extend(builder.prefixes.iter().cloned());
+        self.known_nss.push_scope();
+
+        let mut declared_nss = Vec::new();
+        let mut used_nss = BTreeSet::new();
+        let ns = self.resolve(&builder.name, true, &mut declared_nss)?;
+        // Element bez namespace pod default namespace ho musi oddeklarovat
+        if builder.name.ns_url.is_empty()
+            && self
+                .known_nss
+                .find_by_prefix("")
+                .is_some_and(|ns| !ns.url.is_empty())
+        {
+            let undeclaration = self.new_xmlns("", "");
+            self.known_nss.declare(undeclaration.clone());
+            declared_nss.push(undeclaration);
+        }
+        match &ns {
+            Some(ns) => used_nss.insert(ns.clone()),
+            None => match self.known_nss.find_by_prefix("") {
+                Some(default) => used_nss.insert(default.clone()),
+                None => false,
+            },
+        };
+
+        let mut attrs = BTreeSet::<XmlAttr>::new();
+        for (name, value) in builder.attrs.iter() {
+            check_chars(value)?;
+            let attr = XmlAttr {
+                ns: self.resolve(name, false, &mut declared_nss)?,
+                local_name: name.local_name.clone(),
+                value: value.clone(),
+            };
+            if attrs
+                .iter()
+                .any(|a| a.local_name == attr.local_name && a.get_ns_url() == attr.get_ns_url())
+            {
+                return Err(XmlBuildError::DuplicateAttr(name.clone()));
+            }
+            used_nss.extend(attr.ns.clone());
+            attrs.insert(attr);
+        }
+
+        let inherited_xml_attrs = match parent {
+            Some(p) => self.elems[p]
+                .inherited_xml_attrs
+                .for_children(self.elems[p].attrs.iter()),
+            None => Default::default(),
+        };
+        let id = self.elems.push(XmlElem {
+            ns,
+            local_name: builder.name.local_name.clone(),
+            children: Vec::new(),
+            parent,
+            position: TextPosition::new(),
+            declared_nss,
+            used_nss,
+            attrs,
+            inherited_xml_attrs,
+        });
+        if let Some(parent) = parent {
+            self.elems.get_mut(parent).add_child(XmlNode::Elem(id));
+        }
+
+        for child in builder.children.iter() {
+            match child {
+                BuilderNode::Elem(child) => {
+                    self.add_elem(child, Some(id))?;
+                }
+                BuilderNode::Text(text) => {
+                    check_chars(text)?;
+                    self.elems.get_mut(id).add_text(text.clone());
+                }
+            }
+        }
+
+        self.known_nss.pop_scope();
+        self.preferred.truncate(preferred_len);
+        Ok(id)
+    }
+
+    /// Returns the namespace of the name, `None` for a name without a prefix.
+    /// A namespace which is not bound in scope yet is declared on the element and added to `declared_nss`.
+    /// Only elements (`is_elem`) can be in the default namespace.
+    fn resolve(
+        &mut self,
+        name: &XmlName,
+        is_elem: bool,
+        declared_nss: &mut Vec<Xmlns>,
+    ) -> Result<Option<Xmlns>, XmlBuildError> {
+        if !is_ncname(&name.local_name) {
+            return Err(XmlBuildError::InvalidName(name.local_name.clone()));
+        }
+        let url = name.ns_url.as_str();
+        match url {
+            "" => return Ok(None),
+            NS_XML_URI => return Ok(Some(Xmlns::new_xml())),
+            NS_XMLNS_URI => return Err(XmlBuildError::ReservedNamespace(url.to_owned())),
+            _ => {}
+        }
+
+        // Pri vice prefixech pro tentyz namespace rozhoduje poradi, default namespace je prvni
+        let bound = self
+            .known_nss
+            .iter()
+            .filter(|ns| *ns.url == *url && (is_elem || !ns.is_default()))
+            .min()
+            .cloned();
+        let xmlns = match bound {
+            Some(bound) => bound,
+            None => {
+                let prefix = self.choose_prefix(url, is_elem);
+                let xmlns = self.new_xmlns(&prefix, url);
+                self.known_nss.declare(xmlns.clone());
+                declared_nss.push(xmlns.clone());
+                xmlns
+            }
+        };
+        Ok(Some(xmlns).filter(|ns| !ns.is_default()))
+    }
+
+    /// Chooses a prefix for the namespace which is not bound in scope:
+    /// the innermost preferred one, or the first free `ns1`, `ns2`, ...
+    fn choose_prefix(&self, url: &str, is_elem: bool) -> String {
+        let is_free = |prefix: &str| {
+            self.known_nss
+                .find_by_prefix(prefix)
+                .is_none_or(|ns| ns.url.is_empty())
+        };
+        let preferred = self.preferred.iter().rev().find(|(prefix, ns_url)| {
+            ns_url == url && (is_elem || !prefix.is_empty()) && is_free(prefix)
+        });
+        match preferred {
+            Some((prefix, _)) => prefix.clone(),
+            None => (1..)
+                .map(|n| format!("ns{n}"))
+                .find(|prefix| is_free(prefix))
+                .unwrap_or_default(),
+        }
+    }
+
+    fn new_xmlns(&mut self, prefix: &str, url: &str) -> Xmlns {
+        Xmlns::new(
+            self.interner.intern(url),
+            XmlnsPrefix::new(prefix, &mut self.interner),
+        )
+    }
+}
+
+/// Checks that the text has only characters allowed in XML documents (the `Char` production of XML 1.0).
+/// Control characters other than tab and line endings can't be written even as character references.
+fn check_chars(text: &str) -> Result<(), XmlBuildError> {
+    match text.chars().find(|c| {
+        !matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+    }) {
+        Some(c) => Err(XmlBuildError::InvalidChar(c as u32)),
+        None => Ok(()),
+    }
+}
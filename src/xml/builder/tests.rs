@@ -0,0 +1,181 @@
+#[cfg(test)]
+mod tests {
+    use crate::xml::{
+        builder::{ElementBuilder, XmlBuildError, XmlName},
+        canonicalization::{
+            C14nAlgorithm, C14nSettings, CanonizedXml, InclusivePrefixList, XmlCanonizedDoc,
+        },
+    };
+
+    const SOAP_NS: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+    const WSU_NS: &str =
+        "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd";
+
+    #[test]
+    fn test_prefix_allocation() {
+        let xml = ElementBuilder::new(("urn:a", "Root"))
+            .with_prefix("a", "urn:a")
+            .with_attr(("urn:b", "attr"), "1")
+            .with_child(
+                ElementBuilder::new(("urn:a", "Child"))
+                    .with_attr(("urn:b", "attr"), "2")
+                    .with_child(ElementBuilder::new(("urn:c", "Leaf"))),
+            )
+            .with_child(ElementBuilder::new(("urn:c", "Leaf")))
+            .to_xml_string()
+            .unwrap();
+        // Namespace se deklaruje tam, kde je poprve pouzit, a pak se znovu pouziva
+        assert_eq!(
+            xml,
+            r#"<a:Root xmlns:a="urn:a" xmlns:ns1="urn:b" ns1:attr="1"><a:Child ns1:attr="2"><ns2:Leaf xmlns:ns2="urn:c"></ns2:Leaf></a:Child><ns2:Leaf xmlns:ns2="urn:c"></ns2:Leaf></a:Root>"#
+        );
+    }
+
+    #[test]
+    fn test_default_namespace() {
+        let xml = ElementBuilder::new(("urn:d", "Order"))
+            .with_prefix("d", "urn:d")
+            .with_prefix("", "urn:d")
+            .with_attr("id", "7")
+            .with_attr(("urn:d", "state"), "new")
+            .with_child(ElementBuilder::new(("urn:d", "Item")).with_text("A"))
+            .with_child(
+                ElementBuilder::new("Note")
+                    .with_child(ElementBuilder::new(("urn:d", "Item")).with_text("B")),
+            )
+            .to_xml_string()
+            .unwrap();
+        // Atribut nemuze byt v default namespace, dostane prefix; pod xmlns="" se pouzije tentyz prefix
+        assert_eq!(
+            xml,
+            r#"<Order xmlns="urn:d" xmlns:d="urn:d" id="7" d:state="new"><Item>A</Item><Note xmlns=""><d:Item>B</d:Item></Note></Order>"#
+        );
+    }
+
+    #[test]
+    fn test_preferred_prefix_taken() {
+        let xml = ElementBuilder::new(("urn:a", "Root"))
+            .with_prefix("p", "urn:a")
+            .with_child(
+                ElementBuilder::new(("urn:b", "Child"))
+                    .with_prefix("p", "urn:b")
+                    .with_prefix("ns1", "urn:x"),
+            )
+            .to_xml_string()
+            .unwrap();
+        assert_eq!(
+            xml,
+            r#"<p:Root xmlns:p="urn:a"><ns1:Child xmlns:ns1="urn:b"></ns1:Child></p:Root>"#
+        );
+    }
+
+    #[test]
+    fn test_escaping() {
+        let xml = ElementBuilder::new("a")
+            .with_attr("v", "\"><b x='1'>&\t\n")
+            .with_text("</a><b>&amp;\r\n]]>")
+            .to_xml_string()
+            .unwrap();
+        assert_eq!(
+            xml,
+            r#"<a v="&quot;>&lt;b x='1'>&amp;&#x9;&#xA;">&lt;/a&gt;&lt;b&gt;&amp;amp;&#xD;
+]]&gt;</a>"#
+        );
+
+        // Parsovanim vystupu se ziska puvodni obsah
+        let doc = XmlCanonizedDoc::parse(&xml, &C14nSettings::default()).unwrap();
+        let root = doc.root_element();
+        assert_eq!(root.attribute("", "v"), Some("\"><b x='1'>&\t\n"));
+        assert_eq!(root.text(), "</a><b>&amp;\r\n]]>");
+    }
+
+    #[test]
+    fn test_invalid_content() {
+        let build = |e: ElementBuilder| e.build().unwrap_err();
+        assert_eq!(
+            build(ElementBuilder::new("a b")),
+            XmlBuildError::InvalidName("a b".to_owned())
+        );
+        assert_eq!(
+            build(ElementBuilder::new("a").with_child(ElementBuilder::new(("urn:a", "p:b")))),
+            XmlBuildError::InvalidName("p:b".to_owned())
+        );
+        assert_eq!(
+            build(ElementBuilder::new("a").with_attr("b/><c", "")),
+            XmlBuildError::InvalidName("b/><c".to_owned())
+        );
+        assert_eq!(
+            build(ElementBuilder::new("a").with_prefix("a b", "urn:a")),
+            XmlBuildError::InvalidPrefix("a b".to_owned())
+        );
+        assert_eq!(
+            build(ElementBuilder::new("a").with_prefix("xmlns", "urn:a")),
+            XmlBuildError::InvalidPrefix("xmlns".to_owned())
+        );
+        assert_eq!(
+            build(ElementBuilder::new("a").with_attr(("http://www.w3.org/2000/xmlns/", "p"), "")),
+            XmlBuildError::ReservedNamespace("http://www.w3.org/2000/xmlns/".to_owned())
+        );
+        assert_eq!(
+            build(
+                ElementBuilder::new("a")
+                    .with_attr(("urn:a", "b"), "1")
+                    .with_attr(("urn:a", "b"), "2")
+            ),
+            XmlBuildError::DuplicateAttr(XmlName::new("urn:a", "b"))
+        );
+        assert_eq!(
+            build(ElementBuilder::new("a").with_text("\u{1}")),
+            XmlBuildError::InvalidChar(1)
+        );
+        assert_eq!(
+            build(ElementBuilder::new("a").with_attr("b", "\u{FFFE}")),
+            XmlBuildError::InvalidChar(0xFFFE)
+        );
+        assert_eq!(
+            XmlBuildError::DuplicateAttr(XmlName::new("urn:a", "b")).to_string(),
+            "Attribute '{urn:a}b' is set more than once on the element"
+        );
+    }
+
+    /// The built tree canonicalizes the same way as its XML string parsed again
+    #[test]
+    fn test_built_tree_canonicalization() {
+        let body = ElementBuilder::new((SOAP_NS, "Body"))
+            .with_prefix("soapenv", SOAP_NS)
+            .with_prefix("wsu", WSU_NS)
+            .with_prefix("", "urn:d")
+            .with_attr((WSU_NS, "Id"), "body")
+            .with_attr(("http://www.w3.org/XML/1998/namespace", "lang"), "cs")
+            .with_child(
+                ElementBuilder::new(("urn:d", "Order"))
+                    .with_attr(("urn:m", "state"), "new")
+                    .with_child(ElementBuilder::new("Note").with_text("x")),
+            );
+        let doc = body.build().unwrap();
+        let xml = body.to_xml_string().unwrap();
+        let parsed = XmlCanonizedDoc::parse(&xml, &C14nSettings::default()).unwrap();
+
+        for algorithm in [
+            C14nAlgorithm::Exclusive,
+            C14nAlgorithm::Inclusive,
+            C14nAlgorithm::Inclusive11,
+        ] {
+            let settings = C14nSettings::new(algorithm);
+            assert_eq!(doc.canonize(&settings), parsed.canonize(&settings));
+            let order = doc.root_element().child_elements().next().unwrap();
+            let parsed_order = parsed.root_element().child_elements().next().unwrap();
+            assert_eq!(order.canonize(&settings), parsed_order.canonize(&settings));
+        }
+        assert_eq!(
+            doc.canonize_by_id("body", &C14nSettings::default())
+                .unwrap(),
+            CanonizedXml::new_exclusive_with_prefix_list(&xml, &InclusivePrefixList::default())
+                .unwrap()
+        );
+        assert_eq!(
+            doc.select("//{urn:d}Order/Note", &[]).unwrap()[0].text(),
+            "x"
+        );
+    }
+}
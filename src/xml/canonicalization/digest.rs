@@ -0,0 +1,110 @@
+use super::{C14nSettings, XmlCanonicalizeError, streaming::canonicalize_stream};
+use ring::digest::{Context, Digest};
+use std::io::{Read, Write};
+
+/// Digest algorithms of XML signatures (`ds:DigestMethod`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum DigestAlgorithm {
+    /// SHA-1 (`http://www.w3.org/2000/09/xmldsig#sha1`).
+    /// Only for verifying messages of legacy peers.
+    Sha1,
+
+    /// SHA-256 (`http://www.w3.org/2001/04/xmlenc#sha256`)
+    #[default]
+    Sha256,
+
+    /// SHA-384 (`http://www.w3.org/2001/04/xmldsig-more#sha384`)
+    Sha384,
+
+    /// SHA-512 (`http://www.w3.org/2001/04/xmlenc#sha512`)
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// Returns the algorithm identifier used in `ds:DigestMethod`
+    pub fn uri(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha1 => "http://www.w3.org/2000/09/xmldsig#sha1",
+            DigestAlgorithm::Sha256 => "http://www.w3.org/2001/04/xmlenc#sha256",
+            DigestAlgorithm::Sha384 => "http://www.w3.org/2001/04/xmldsig-more#sha384",
+            DigestAlgorithm::Sha512 => "http://www.w3.org/2001/04/xmlenc#sha512",
+        }
+    }
+
+    /// Finds the algorithm by its identifier. Returns `None` for unsupported algorithms.
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        [
+            DigestAlgorithm::Sha1,
+            DigestAlgorithm::Sha256,
+            DigestAlgorithm::Sha384,
+            DigestAlgorithm::Sha512,
+        ]
+        .into_iter()
+        .find(|a| a.uri() == uri)
+    }
+
+    /// Returns the matching `ring` algorithm
+    pub fn ring_algorithm(&self) -> &'static ring::digest::Algorithm {
+        match self {
+            DigestAlgorithm::Sha1 => &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+            DigestAlgorithm::Sha256 => &ring::digest::SHA256,
+            DigestAlgorithm::Sha384 => &ring::digest::SHA384,
+            DigestAlgorithm::Sha512 => &ring::digest::SHA512,
+        }
+    }
+}
+
+/// Adapter which feeds the canonical XML written by `canonicalize_stream` (as `io::Write`)
+/// or by the tree of `XmlCanonizedDoc` (as `fmt::Write`) into a `ring::digest::Context`
+#[derive(Clone)]
+pub struct DigestWriter(Context);
+
+impl DigestWriter {
+    /// Creates the writer with a new digest context
+    pub fn new(algorithm: DigestAlgorithm) -> Self {
+        Self(Context::new(algorithm.ring_algorithm()))
+    }
+
+    /// Finishes the digest of everything written so far
+    pub fn finish(self) -> Digest {
+        self.0.finish()
+    }
+}
+
+/// Continues an existing digest context
+impl From<Context> for DigestWriter {
+    fn from(context: Context) -> Self {
+        Self(context)
+    }
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::fmt::Write for DigestWriter {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.update(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Reads a XML document and computes the digest of its canonical form.
+/// The canonical XML is fed straight into the digest, it is never held in memory as a whole.
+/// See `canonicalize_stream`.
+pub fn digest_stream<R: Read>(
+    reader: R,
+    settings: &C14nSettings,
+    algorithm: DigestAlgorithm,
+) -> Result<Digest, XmlCanonicalizeError> {
+    let mut writer = DigestWriter::new(algorithm);
+    canonicalize_stream(reader, &mut writer, settings)?;
+    Ok(writer.finish())
+}
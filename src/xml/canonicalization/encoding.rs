@@ -0,0 +1,87 @@
+use super::XmlCanonicalizeError;
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use std::borrow::Cow;
+
+/// Decodes a XML document to UTF-8, as canonical XML is always UTF-8.
+/// The encoding is detected as described in the XML specification (appendix F):
+/// - a byte order mark (UTF-8, UTF-16LE, UTF-16BE),
+/// - UTF-16 without a BOM, recognized by the bytes of the leading `<?`,
+/// - the `encoding` of the XML declaration (e.g. `ISO-8859-2`, `windows-1250`),
+/// - UTF-8 otherwise.
+///
+/// Encoding labels are resolved as by web browsers, so `US-ASCII` is read as `windows-1252`.
+/// Only `ISO-8859-1` is decoded by its own table (byte `n` is the character `U+00nn`),
+/// browsers read it as `windows-1252` too, which differs in the bytes `0x80`–`0x9F`.
+/// The XML declaration is kept, the parser ignores its `encoding`.
+pub(super) fn decode_xml(bytes: &[u8]) -> Result<Cow<'_, str>, XmlCanonicalizeError> {
+    let (encoding, bom_len) = match Encoding::for_bom(bytes) {
+        Some(found) => found,
+        None if declared_encoding(bytes).is_some_and(is_latin1) => {
+            return Ok(Cow::Owned(bytes.iter().copied().map(char::from).collect()));
+        }
+        None => (detect_without_bom(bytes)?, 0),
+    };
+    encoding
+        .decode_without_bom_handling_and_without_replacement(&bytes[bom_len..])
+        .ok_or_else(|| XmlCanonicalizeError::MalformedEncoding(encoding.name().to_owned()))
+}
+
+/// Detects the encoding of a document without a byte order mark
+fn detect_without_bom(bytes: &[u8]) -> Result<&'static Encoding, XmlCanonicalizeError> {
+    // Prvni znaky "<?" v UTF-16 bez BOM
+    match bytes {
+        [0x3C, 0x00, 0x3F, 0x00, ..] => return Ok(UTF_16LE),
+        [0x00, 0x3C, 0x00, 0x3F, ..] => return Ok(UTF_16BE),
+        _ => {}
+    }
+
+    let Some(label) = declared_encoding(bytes) else {
+        return Ok(UTF_8);
+    };
+    match Encoding::for_label_no_replacement(label) {
+        // UTF-16 by se muselo poznat uz podle BOM nebo prvnich bajtu
+        Some(encoding) if encoding.is_ascii_compatible() => Ok(encoding),
+        _ => Err(XmlCanonicalizeError::UnsupportedEncoding(
+            String::from_utf8_lossy(label).into_owned(),
+        )),
+    }
+}
+
+/// Returns `true` for the IANA names of `ISO-8859-1`
+fn is_latin1(label: &[u8]) -> bool {
+    [
+        "ISO-8859-1",
+        "ISO_8859-1",
+        "ISO_8859-1:1987",
+        "iso-ir-100",
+        "latin1",
+        "l1",
+        "IBM819",
+        "CP819",
+        "csISOLatin1",
+    ]
+    .iter()
+    .any(|name| label.eq_ignore_ascii_case(name.as_bytes()))
+}
+
+/// Returns the value of `encoding` in the XML declaration at the start of the document, if any.
+/// Only ASCII compatible encodings can be read this way.
+fn declared_encoding(bytes: &[u8]) -> Option<&[u8]> {
+    let declaration = bytes.strip_prefix(b"<?xml")?;
+    if !declaration.first()?.is_ascii_whitespace() {
+        return None;
+    }
+    let end = declaration.windows(2).position(|w| w == b"?>")?;
+    let declaration = &declaration[..end];
+
+    let start = declaration.windows(8).position(|w| w == b"encoding")? + 8;
+    let value = declaration[start..].trim_ascii_start().strip_prefix(b"=")?;
+    let value = value.trim_ascii_start();
+    let quote = *value.first()?;
+    if quote != b'"' && quote != b'\'' {
+        return None;
+    }
+    let value = &value[1..];
+    let len = value.iter().position(|b| *b == quote)?;
+    Some(&value[..len])
+}
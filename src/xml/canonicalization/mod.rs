@@ -1,8 +1,23 @@
+pub mod digest;
+mod encoding;
+pub mod parser_limits;
+pub mod qname_detection;
+pub mod source_location;
+pub mod streaming;
+#[allow(clippy::module_inception)]
 mod tests;
 pub mod xml_canonized_doc;
-use std::fmt::Display;
+use digest::{DigestAlgorithm, DigestWriter};
+use parser_limits::ParserLimits;
+use qname_detection::QNameDetection;
+use source_location::SourceLocation;
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Write},
+    io::Read,
+};
 
-use xml_canonized_doc::XmlCanonizedDoc;
+pub use xml_canonized_doc::XmlCanonizedDoc;
 
 #[derive(thiserror::Error, Debug, PartialEq, Eq)]
 pub enum XmlCanonicalizeError {
@@ -15,10 +30,333 @@ pub enum XmlCanonicalizeError {
     #[error("Empty string is not a valid XML document")]
     EmptyDoc,
 
+    /// Located at the `ds:Reference` when its `URI` is dereferenced,
+    /// an ID passed by the caller has no position in the document
+    #[error("No element with ID '{0}' found in the XML document")]
+    IdNotFound(String),
+
+    #[error("ID '{0}' is not unique in the XML document")]
+    DuplicateId(String),
+
+    /// Located at the `ds:Reference` when its `URI` is dereferenced,
+    /// a URI passed by the caller has no position in the document
+    #[error("Unsupported reference URI: '{0}'. Only same-document references are supported.")]
+    UnsupportedReferenceUri(String),
+
+    /// Located at the `ds:Reference`, or at the apex of the referenced node set
+    /// when only the reference URI is known
+    #[error("No ds:Signature element found in the referenced XML")]
+    SignatureNotFound,
+
+    #[error("More than one ds:Signature element found in the referenced XML")]
+    AmbiguousSignature,
+
+    #[error("Failed to write the canonical XML: {0}")]
+    WriteError(String),
+
+    #[error("XML document is nested deeper than the limit of {0} elements")]
+    TooDeep(usize),
+
+    #[error("XML document is larger than the limit of {0} bytes")]
+    TooLarge(u64),
+
+    #[error("XML element has more than the limit of {0} attributes")]
+    TooManyAttributes(usize),
+
+    #[error("XML element has more than the limit of {0} namespace declarations in scope")]
+    TooManyNamespaces(usize),
+
+    #[error("XML text node is longer than the limit of {0} bytes")]
+    TextTooLong(usize),
+
+    #[error("XML document type declarations (DTD) are not allowed")]
+    DtdNotAllowed,
+
+    #[error("Unsupported XML encoding: '{0}'")]
+    UnsupportedEncoding(String),
+
+    #[error("XML document is not valid {0}")]
+    MalformedEncoding(String),
+
+    #[error("Failed to read the XML document: {0}")]
+    SourceReadError(String),
+
     #[error(
-        "Failed to parse XML namespace prefix: '{0}'. Must be ASCII and no longer than 16 characters."
+        "QName detection needs the document tree, it is not supported by streaming canonicalization"
     )]
-    InvalidXmlnsPrefix(String),
+    QNameDetectionNotStreamable,
+
+    #[error("{error} at {location}")]
+    Located {
+        error: Box<XmlCanonicalizeError>,
+        location: SourceLocation,
+    },
+}
+
+impl XmlCanonicalizeError {
+    /// Returns where in the XML document the error occurred, if it is known
+    pub fn location(&self) -> Option<&SourceLocation> {
+        match self {
+            XmlCanonicalizeError::Located { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// Returns the error without its location
+    pub fn kind(&self) -> &XmlCanonicalizeError {
+        match self {
+            XmlCanonicalizeError::Located { error, .. } => error,
+            e => e,
+        }
+    }
+
+    /// Attaches the location to the error, unless it already has one
+    pub(crate) fn at(self, location: SourceLocation) -> Self {
+        match self {
+            XmlCanonicalizeError::Located { .. } => self,
+            e => XmlCanonicalizeError::Located {
+                error: Box::new(e),
+                location,
+            },
+        }
+    }
+}
+
+/// XML canonicalization algorithms supported by this library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum C14nAlgorithm {
+    /// Exclusive XML Canonicalization 1.0 (`http://www.w3.org/2001/10/xml-exc-c14n#`).
+    /// Only namespaces visibly used by an element or its attributes are rendered.
+    #[default]
+    Exclusive,
+
+    /// Canonical XML 1.0 (`http://www.w3.org/TR/2001/REC-xml-c14n-20010315`).
+    /// Every namespace in scope is rendered on the first output element where it appears.
+    Inclusive,
+
+    /// Canonical XML 1.1 (`http://www.w3.org/2006/12/xml-c14n11`).
+    /// Same as Canonical XML 1.0, but `xml:id` is not inherited and `xml:base` is fixed up
+    /// when a subtree is canonicalized.
+    Inclusive11,
+
+    /// Exclusive XML Canonicalization 1.0 with comments
+    /// (`http://www.w3.org/2001/10/xml-exc-c14n#WithComments`).
+    ExclusiveWithComments,
+
+    /// Canonical XML 1.0 with comments
+    /// (`http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments`).
+    InclusiveWithComments,
+
+    /// Canonical XML 1.1 with comments (`http://www.w3.org/2006/12/xml-c14n11#WithComments`).
+    Inclusive11WithComments,
+}
+
+impl C14nAlgorithm {
+    /// Returns the algorithm identifier used in `ds:CanonicalizationMethod` and `ds:Transform`
+    pub fn uri(&self) -> &'static str {
+        match self {
+            C14nAlgorithm::Exclusive => "http://www.w3.org/2001/10/xml-exc-c14n#",
+            C14nAlgorithm::Inclusive => "http://www.w3.org/TR/2001/REC-xml-c14n-20010315",
+            C14nAlgorithm::Inclusive11 => "http://www.w3.org/2006/12/xml-c14n11",
+            C14nAlgorithm::ExclusiveWithComments => {
+                "http://www.w3.org/2001/10/xml-exc-c14n#WithComments"
+            }
+            C14nAlgorithm::InclusiveWithComments => {
+                "http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments"
+            }
+            C14nAlgorithm::Inclusive11WithComments => {
+                "http://www.w3.org/2006/12/xml-c14n11#WithComments"
+            }
+        }
+    }
+
+    /// Returns `true` for the `#WithComments` variants, which keep comments in the output
+    pub fn with_comments(&self) -> bool {
+        matches!(
+            self,
+            C14nAlgorithm::ExclusiveWithComments
+                | C14nAlgorithm::InclusiveWithComments
+                | C14nAlgorithm::Inclusive11WithComments
+        )
+    }
+
+    /// Returns the same algorithm without comments, used when dereferencing a same-document reference
+    pub fn without_comments(&self) -> Self {
+        match self {
+            C14nAlgorithm::ExclusiveWithComments => C14nAlgorithm::Exclusive,
+            C14nAlgorithm::InclusiveWithComments => C14nAlgorithm::Inclusive,
+            C14nAlgorithm::Inclusive11WithComments => C14nAlgorithm::Inclusive11,
+            algorithm => *algorithm,
+        }
+    }
+
+    /// Returns `true` for the exclusive algorithms, which take the `InclusiveNamespaces PrefixList`
+    pub fn is_exclusive(&self) -> bool {
+        matches!(
+            self,
+            C14nAlgorithm::Exclusive | C14nAlgorithm::ExclusiveWithComments
+        )
+    }
+
+    /// Finds the algorithm by its identifier. Returns `None` for unsupported algorithms.
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        [
+            C14nAlgorithm::Exclusive,
+            C14nAlgorithm::Inclusive,
+            C14nAlgorithm::Inclusive11,
+            C14nAlgorithm::ExclusiveWithComments,
+            C14nAlgorithm::InclusiveWithComments,
+            C14nAlgorithm::Inclusive11WithComments,
+        ]
+        .into_iter()
+        .find(|a| a.uri() == uri)
+    }
+}
+
+/// The `PrefixList` parameter of exclusive canonicalization (`<ec:InclusiveNamespaces>`).
+/// Namespaces with these prefixes are rendered as in inclusive canonicalization,
+/// even if they are not visibly used. The default namespace is written as `#default`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InclusivePrefixList(BTreeSet<String>);
+
+impl InclusivePrefixList {
+    /// Token which stands for the default namespace in the `PrefixList`
+    const DEFAULT_TOKEN: &str = "#default";
+
+    /// Creates the list from prefixes. Use `""` or `#default` for the default namespace.
+    pub fn new<S: AsRef<str>>(prefixes: impl IntoIterator<Item = S>) -> Self {
+        Self(
+            prefixes
+                .into_iter()
+                .map(|p| match p.as_ref() {
+                    Self::DEFAULT_TOKEN => String::new(),
+                    p => p.to_owned(),
+                })
+                .collect(),
+        )
+    }
+
+    /// Parses the whitespace separated value of the `PrefixList` attribute
+    pub fn parse(prefix_list: &str) -> Self {
+        Self::new(prefix_list.split_whitespace())
+    }
+
+    /// Returns `true` if the namespace prefix is in the list (`""` for the default namespace)
+    pub fn contains(&self, prefix: &str) -> bool {
+        self.0.contains(prefix)
+    }
+
+    /// Returns `true` if the list has no prefixes
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates over the prefixes in code-point order (`""` for the default namespace)
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    /// Returns the list with the prefixes of both lists
+    pub fn union(&self, other: &InclusivePrefixList) -> Self {
+        Self(self.0.union(&other.0).cloned().collect())
+    }
+}
+
+/// Returns the value of the `PrefixList` attribute
+impl Display for InclusivePrefixList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut first = true;
+        for prefix in self.0.iter() {
+            if !first {
+                write!(f, " ")?;
+            }
+            first = false;
+            match prefix.as_str() {
+                "" => write!(f, "{}", Self::DEFAULT_TOKEN)?,
+                p => write!(f, "{p}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How whitespace-only text nodes are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum WhitespaceMode {
+    /// Every whitespace text node inside the root element is kept, as required by the C14N specifications.
+    #[default]
+    Preserve,
+
+    /// Whitespace-only text nodes are dropped, e.g. the indentation between elements.
+    /// The output is not interoperable with other C14N implementations,
+    /// unless the input contains no such whitespace.
+    Normalize,
+}
+
+/// Parameters of a canonicalization run.
+/// The default is exclusive canonicalization without comments which preserves whitespace.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct C14nSettings {
+    pub(crate) algorithm: C14nAlgorithm,
+
+    /// Only used by exclusive canonicalization (with or without comments)
+    pub(crate) inclusive_prefixes: InclusivePrefixList,
+
+    pub(crate) whitespace: WhitespaceMode,
+
+    pub(crate) limits: ParserLimits,
+
+    /// Only used by exclusive canonicalization, adds the detected prefixes to `inclusive_prefixes`
+    pub(crate) qname_detection: QNameDetection,
+}
+
+impl C14nSettings {
+    /// Creates the settings for the given algorithm
+    pub fn new(algorithm: C14nAlgorithm) -> Self {
+        Self {
+            algorithm,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the `InclusiveNamespaces PrefixList` parameter of exclusive canonicalization
+    pub fn with_inclusive_prefixes(mut self, prefix_list: InclusivePrefixList) -> Self {
+        self.inclusive_prefixes = prefix_list;
+        self
+    }
+
+    /// Sets how whitespace-only text nodes are treated
+    pub fn with_whitespace(mut self, whitespace: WhitespaceMode) -> Self {
+        self.whitespace = whitespace;
+        self
+    }
+
+    /// Sets the limits applied while reading the XML document
+    pub fn with_limits(mut self, limits: ParserLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the detection of prefixes used in QName-valued content (e.g. `xsi:type="tns:OrderType"`).
+    /// Exclusive canonicalization of a document tree then adds them to the `InclusiveNamespaces PrefixList`,
+    /// the resulting list has to be sent with the signature (see `XmlCanonizedDoc::inclusive_prefixes_for`).
+    pub fn with_qname_detection(mut self, qname_detection: QNameDetection) -> Self {
+        self.qname_detection = qname_detection;
+        self
+    }
+
+    /// Returns the canonicalization algorithm
+    pub fn algorithm(&self) -> C14nAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the settings with the algorithm without comments
+    pub(crate) fn without_comments(&self) -> Self {
+        Self {
+            algorithm: self.algorithm.without_comments(),
+            ..self.clone()
+        }
+    }
 }
 
 /// A string that represents valid, canonicalized XML.
@@ -26,14 +364,104 @@ pub enum XmlCanonicalizeError {
 pub struct CanonizedXml(String);
 
 impl CanonizedXml {
-    /// Reads a XML document and converts it to its canonical form.
+    /// Reads a XML document and converts it to its canonical form
+    /// using exclusive canonicalization.
     /// The XML reading or conversion may fail.
     pub fn new(xml: &str) -> Result<Self, XmlCanonicalizeError> {
+        Self::new_with_algorithm(xml, C14nAlgorithm::Exclusive)
+    }
+
+    /// Reads a XML document and converts it to its canonical form using the given algorithm.
+    /// The XML reading or conversion may fail.
+    pub fn new_with_algorithm(
+        xml: &str,
+        algorithm: C14nAlgorithm,
+    ) -> Result<Self, XmlCanonicalizeError> {
+        Self::new_with_settings(xml, &C14nSettings::new(algorithm))
+    }
+
+    /// Reads a XML document and converts it to its canonical form
+    /// using exclusive canonicalization with the `InclusiveNamespaces PrefixList` parameter.
+    /// The XML reading or conversion may fail.
+    pub fn new_exclusive_with_prefix_list(
+        xml: &str,
+        prefix_list: &InclusivePrefixList,
+    ) -> Result<Self, XmlCanonicalizeError> {
+        let settings = C14nSettings::new(C14nAlgorithm::Exclusive)
+            .with_inclusive_prefixes(prefix_list.clone());
+        Self::new_with_settings(xml, &settings)
+    }
+
+    /// Reads a XML document and converts it to its canonical form using the given settings.
+    /// The XML reading or conversion may fail.
+    pub fn new_with_settings(
+        xml: &str,
+        settings: &C14nSettings,
+    ) -> Result<Self, XmlCanonicalizeError> {
         // stromová struktura kanonizovaného dokumentu. Při parsování může dojít k chybě
-        let canonized_xml_tree = XmlCanonizedDoc::parse(xml)?;
+        let canonized_xml_tree = XmlCanonizedDoc::parse(xml, settings)?;
         // Převod stromu XML dokumentu na string
-        let canonized_xml = canonized_xml_tree.write_xml_as_string();
-        Ok(Self(canonized_xml))
+        Ok(canonized_xml_tree.canonize(settings))
+    }
+
+    /// Decodes a XML document from bytes and converts it to its canonical form using the given settings.
+    /// The encoding is taken from the byte order mark or the XML declaration, UTF-8 is the default.
+    /// UTF-16 and the single-byte encodings like `ISO-8859-2` or `windows-1250` are supported.
+    /// The decoding, XML reading or conversion may fail.
+    pub fn from_bytes(xml: &[u8], settings: &C14nSettings) -> Result<Self, XmlCanonicalizeError> {
+        let max_bytes = settings.limits.max_total_bytes();
+        if xml.len() as u64 > max_bytes {
+            return Err(XmlCanonicalizeError::TooLarge(max_bytes));
+        }
+        Self::new_with_settings(&encoding::decode_xml(xml)?, settings)
+    }
+
+    /// Reads a XML document in any supported encoding and converts it to its canonical form
+    /// using the given settings. See `from_bytes`.
+    /// The document is read as a whole, up to the size limit of the settings.
+    pub fn from_reader<R: Read>(
+        reader: R,
+        settings: &C14nSettings,
+    ) -> Result<Self, XmlCanonicalizeError> {
+        let max_bytes = settings.limits.max_total_bytes();
+        let mut xml = Vec::new();
+        reader
+            .take(max_bytes.saturating_add(1))
+            .read_to_end(&mut xml)
+            .map_err(|e| XmlCanonicalizeError::SourceReadError(e.to_string()))?;
+        Self::from_bytes(&xml, settings)
+    }
+
+    /// Reads a XML document and converts the element with the given `wsu:Id`, `Id` or `xml:id`
+    /// to its canonical form, with the namespace context inherited from its ancestors.
+    /// Comments are left out, see `XmlCanonizedDoc::canonize_by_id`.
+    /// Use `XmlCanonizedDoc` to canonicalize more subtrees of one document.
+    /// The XML reading fails, or the ID is not found or is not unique.
+    pub fn new_subtree_by_id(
+        xml: &str,
+        id: &str,
+        settings: &C14nSettings,
+    ) -> Result<Self, XmlCanonicalizeError> {
+        XmlCanonizedDoc::parse(xml, settings)?.canonize_by_id(id, settings)
+    }
+
+    /// Reads a XML document, applies the enveloped-signature transform to the same-document
+    /// reference `uri` (`""` or `#id`) and converts the result to its canonical form.
+    /// See `XmlCanonizedDoc::canonize_enveloped`.
+    pub fn new_enveloped(
+        xml: &str,
+        uri: &str,
+        settings: &C14nSettings,
+    ) -> Result<Self, XmlCanonicalizeError> {
+        XmlCanonizedDoc::parse(xml, settings)?.canonize_enveloped(uri, settings)
+    }
+
+    /// Computes the digest of the canonical XML
+    pub fn digest(&self, algorithm: DigestAlgorithm) -> ring::digest::Digest {
+        let mut writer = DigestWriter::new(algorithm);
+        // Zapis do digestu nemuze selhat
+        let _ = writer.write_str(self.as_str());
+        writer.finish()
     }
 
     /// Returns its XML as UTF-8 bytes
@@ -47,6 +475,7 @@ impl CanonizedXml {
     }
 
     /// Moves its inner value to a regular string
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(self) -> String {
         self.0
     }
@@ -0,0 +1,307 @@
+use super::{
+    XmlCanonicalizeError,
+    source_location::{ElementPath, SourceLocation},
+    xml_canonized_doc::NormalizedInput,
+};
+use std::{collections::BTreeMap, io::Read};
+use xml::{
+    EventReader,
+    common::{Position, TextPosition},
+    namespace::Namespace,
+    reader::XmlEvent,
+};
+
+/// Limits applied while reading an untrusted XML document.
+/// Exceeding any of them fails the canonicalization before the document exhausts memory.
+/// The defaults are generous for SOAP messages, DTDs are not allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParserLimits {
+    max_depth: usize,
+    max_total_bytes: u64,
+    max_attributes: usize,
+    max_namespaces: usize,
+    max_text_length: usize,
+    max_entity_expansion_length: usize,
+    allow_dtd: bool,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 256,
+            max_total_bytes: 64 * 1024 * 1024,
+            max_attributes: 256,
+            max_namespaces: 256,
+            max_text_length: 32 * 1024 * 1024,
+            max_entity_expansion_length: 64 * 1024,
+            allow_dtd: false,
+        }
+    }
+}
+
+impl ParserLimits {
+    /// Sets the maximum nesting depth of elements (the root element has depth 1)
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the maximum size of the input in bytes
+    pub fn with_max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.max_total_bytes = max_total_bytes;
+        self
+    }
+
+    /// Sets the maximum number of attributes of one element, not counting namespace declarations
+    pub fn with_max_attributes(mut self, max_attributes: usize) -> Self {
+        self.max_attributes = max_attributes;
+        self
+    }
+
+    /// Sets the maximum number of namespace declarations in scope of one element
+    pub fn with_max_namespaces(mut self, max_namespaces: usize) -> Self {
+        self.max_namespaces = max_namespaces;
+        self
+    }
+
+    /// Sets the maximum length of one text node in bytes
+    pub fn with_max_text_length(mut self, max_text_length: usize) -> Self {
+        self.max_text_length = max_text_length;
+        self
+    }
+
+    /// Sets the maximum length of the text an entity reference expands to.
+    /// Only applies when a DTD is allowed, entities are declared in it.
+    pub fn with_max_entity_expansion_length(mut self, max_entity_expansion_length: usize) -> Self {
+        self.max_entity_expansion_length = max_entity_expansion_length;
+        self
+    }
+
+    /// Returns the maximum size of the input in bytes
+    pub fn max_total_bytes(&self) -> u64 {
+        self.max_total_bytes
+    }
+
+    /// Returns the maximum number of attributes of one element
+    pub fn max_attributes(&self) -> usize {
+        self.max_attributes
+    }
+
+    /// Returns the maximum length of the text an entity reference expands to
+    pub fn max_entity_expansion_length(&self) -> usize {
+        self.max_entity_expansion_length
+    }
+
+    /// Returns whether a document type declaration is allowed
+    pub fn is_dtd_allowed(&self) -> bool {
+        self.allow_dtd
+    }
+
+    /// Allows a document type declaration. Entities declared in it are expanded by the reader
+    /// within `max_entity_expansion_length`. Only for trusted input.
+    pub fn with_dtd_allowed(mut self, allow_dtd: bool) -> Self {
+        self.allow_dtd = allow_dtd;
+        self
+    }
+}
+
+/// Source which counts the bytes read and ends the input once the limit is exceeded.
+/// The reader then fails on the unexpected end, `LimitedEvents` reports the exceeded limit instead.
+pub(super) struct LimitedRead<R> {
+    inner: R,
+    bytes_read: u64,
+    max_total_bytes: u64,
+}
+
+impl<R> LimitedRead<R> {
+    pub fn new(inner: R, max_total_bytes: u64) -> Self {
+        Self {
+            inner,
+            bytes_read: 0,
+            max_total_bytes,
+        }
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.bytes_read > self.max_total_bytes
+    }
+}
+
+impl<R: Read> Read for LimitedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.is_exceeded() {
+            return Ok(0);
+        }
+        let n = self.inner.read(buf)?;
+        self.bytes_read += n as u64;
+        match self.is_exceeded() {
+            true => Ok(0),
+            false => Ok(n),
+        }
+    }
+}
+
+/// Events of the `xml-rs` reader, checked against the `ParserLimits`.
+/// Iterates until the end of the document or the first error.
+/// Errors are located at the position of the last event and the path of the element being read.
+/// The `namespace` of a start element holds only the namespaces declared by the element,
+/// not all the namespaces in scope reported by the reader.
+pub(super) struct LimitedEvents<R: Read> {
+    reader: EventReader<NormalizedInput<LimitedRead<R>>>,
+    limits: ParserLimits,
+
+    /// Path of the currently open element
+    path: ElementPath,
+
+    /// Namespaces in scope of the open elements, with the number of declarations counted by the limit
+    namespaces: Vec<(Namespace, usize)>,
+
+    /// The last event was an end element, it is left on the next event
+    end_pending: bool,
+    finished: bool,
+}
+
+impl<R: Read> LimitedEvents<R> {
+    pub fn new(reader: EventReader<NormalizedInput<LimitedRead<R>>>, limits: ParserLimits) -> Self {
+        Self {
+            reader,
+            limits,
+            path: ElementPath::default(),
+            namespaces: Vec::new(),
+            end_pending: false,
+            finished: false,
+        }
+    }
+
+    /// Returns the position of the last event
+    pub fn position(&self) -> TextPosition {
+        self.reader.position()
+    }
+
+    /// Attaches the current location to an error raised while processing the last event.
+    /// A reading error keeps the position reported by the reader, which is past the last event.
+    pub fn locate(&self, error: XmlCanonicalizeError) -> XmlCanonicalizeError {
+        let position = match &error {
+            XmlCanonicalizeError::XmlReadError(e) => e.position(),
+            _ => self.position(),
+        };
+        error.at(SourceLocation::new(position, self.path.to_string()))
+    }
+
+    /// Checks the event against the limits
+    /// and replaces the namespaces in scope of a start element by the ones it declares
+    fn check(&mut self, event: &mut XmlEvent) -> Result<(), XmlCanonicalizeError> {
+        let limits = &self.limits;
+        match event {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                self.path.start(name);
+                if self.path.depth() > limits.max_depth {
+                    return Err(XmlCanonicalizeError::TooDeep(limits.max_depth));
+                }
+                if attributes.len() > limits.max_attributes {
+                    return Err(XmlCanonicalizeError::TooManyAttributes(
+                        limits.max_attributes,
+                    ));
+                }
+                let (parent, parent_count) = match self.namespaces.last() {
+                    Some((parent, count)) => (parent, *count),
+                    None => (&NO_NAMESPACES, 0),
+                };
+                let (declared, count) = declared_namespaces(namespace, parent, parent_count);
+                if count > limits.max_namespaces {
+                    return Err(XmlCanonicalizeError::TooManyNamespaces(
+                        limits.max_namespaces,
+                    ));
+                }
+                let in_scope = std::mem::replace(namespace, declared);
+                self.namespaces.push((in_scope, count));
+            }
+            XmlEvent::EndElement { .. } => {
+                self.end_pending = true;
+            }
+            XmlEvent::Characters(text) | XmlEvent::Whitespace(text)
+                if text.len() > limits.max_text_length =>
+            {
+                return Err(XmlCanonicalizeError::TextTooLong(limits.max_text_length));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for LimitedEvents<R> {
+    type Item = Result<XmlEvent, XmlCanonicalizeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        if std::mem::take(&mut self.end_pending) {
+            self.path.end();
+            self.namespaces.pop();
+        }
+        let event = self.reader.next();
+        // Konec vstupu mohl byt umely, chyba ani konec dokumentu pak neplati
+        if self.reader.source().get_ref().is_exceeded() {
+            self.finished = true;
+            let error = XmlCanonicalizeError::TooLarge(self.limits.max_total_bytes);
+            return Some(Err(self.locate(error)));
+        }
+        // Vstup skoncil na zacatku zakazaneho DTD
+        if self.reader.source().is_doctype_rejected() {
+            self.finished = true;
+            return Some(Err(self.locate(XmlCanonicalizeError::DtdNotAllowed)));
+        }
+        let checked = match event {
+            Ok(XmlEvent::EndDocument) => {
+                self.finished = true;
+                return None;
+            }
+            Ok(mut event) => self.check(&mut event).map(|_| event),
+            Err(e) => Err(e.into()),
+        };
+        let checked = checked.map_err(|e| self.locate(e));
+        self.finished = checked.is_err();
+        Some(checked)
+    }
+}
+
+/// Scope of the root element
+static NO_NAMESPACES: Namespace = Namespace(BTreeMap::new());
+
+/// Returns the namespaces declared by an element: those of its scope `in_scope` which are not
+/// in the scope of its parent with the same URI. `xml-rs` reports only the whole scope,
+/// both are sorted by prefix, so one pass over them suffices.
+/// Also returns the number of declarations in scope counted by the limit, from the parent's `parent_count`.
+fn declared_namespaces(
+    in_scope: &Namespace,
+    parent: &Namespace,
+    parent_count: usize,
+) -> (Namespace, usize) {
+    let mut declared = BTreeMap::new();
+    let mut count = parent_count;
+    let mut inherited = parent.0.iter().peekable();
+    for (prefix, url) in in_scope.0.iter() {
+        while inherited.next_if(|(p, _)| *p < prefix).is_some() {}
+        let parent_url = inherited.next_if(|(p, _)| *p == prefix).map(|(_, u)| u);
+        if parent_url == Some(url) {
+            continue;
+        }
+        // Predefinovany prefix nahrazuje deklaraci rodice
+        count += is_counted(prefix, url) as usize;
+        count -= parent_url.is_some_and(|u| is_counted(prefix, u)) as usize;
+        declared.insert(prefix.clone(), url.clone());
+    }
+    (Namespace(declared), count)
+}
+
+/// Whether the namespace is counted by the limit: not the implicit `xml`, `xmlns`
+/// and empty default namespaces
+fn is_counted(prefix: &str, url: &str) -> bool {
+    !matches!((prefix, url), ("xml" | "xmlns", _) | ("", ""))
+}
@@ -0,0 +1,121 @@
+use super::InclusivePrefixList;
+use crate::tools::is_ncname;
+use crate::xml::{
+    xml_attr::XmlAttr,
+    xml_elem::{NsScopes, XmlElem, XmlElemArena, XmlNode},
+    xmlns_scope::XmlnsScope,
+};
+use std::collections::BTreeSet;
+
+/// Namespace of XML Schema instance attributes (`xsi:type`, ...)
+pub const NS_XSI_URI: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// Detection of namespace prefixes used in QName-valued content, e.g. `xsi:type="tns:OrderType"`.
+/// Exclusive canonicalization only renders the namespaces used by element and attribute names,
+/// so the namespace of such a value is lost when an intermediary moves or strips the declarations.
+/// The detected prefixes are added to the `InclusiveNamespaces PrefixList` of exclusive canonicalization,
+/// see `XmlCanonizedDoc::inclusive_prefixes_for`. Inclusive algorithms render every namespace anyway.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QNameDetection {
+    /// Only element and attribute names use namespaces, as the C14N specifications assume
+    #[default]
+    Off,
+
+    /// The values of these attributes are QNames, given by namespace URI and local name
+    /// (`""` for attributes without a prefix). A value without a prefix is in the default namespace.
+    Attributes(BTreeSet<(String, String)>),
+
+    /// Every attribute value and text which looks like a prefixed QName (`prefix:name`,
+    /// with the prefix bound in scope) is taken as one. Values without a prefix are not detected.
+    Heuristic,
+}
+
+impl QNameDetection {
+    /// Detects QNames in the values of the given attributes, named by namespace URI and local name
+    pub fn attributes<S: Into<String>>(names: impl IntoIterator<Item = (S, S)>) -> Self {
+        Self::Attributes(
+            names
+                .into_iter()
+                .map(|(ns_url, local_name)| (ns_url.into(), local_name.into()))
+                .collect(),
+        )
+    }
+
+    /// Detects QNames in the values of `xsi:type`, the usual QName-valued attribute
+    pub fn xsi_type() -> Self {
+        Self::attributes([(NS_XSI_URI, "type")])
+    }
+
+    /// Returns the prefixes of the QNames in the subtree of `apex` which are bound in scope
+    /// (`""` for the default namespace)
+    pub(crate) fn find_prefixes(
+        &self,
+        apex: &XmlElem,
+        arena: &XmlElemArena,
+    ) -> InclusivePrefixList {
+        let mut prefixes = BTreeSet::new();
+        if *self != QNameDetection::Off {
+            let mut known = NsScopes::for_apex(apex, arena).known;
+            self.find_prefixes_recur(apex, arena, &mut known, &mut prefixes);
+        }
+        InclusivePrefixList::new(prefixes)
+    }
+
+    fn find_prefixes_recur<'a>(
+        &self,
+        elem: &'a XmlElem,
+        arena: &'a XmlElemArena,
+        known: &mut XmlnsScope,
+        prefixes: &mut BTreeSet<&'a str>,
+    ) {
+        known.push_scope();
+        for ns in elem.declared_nss.iter() {
+            known.declare(ns.clone());
+        }
+
+        for attr in elem.attrs.iter().filter(|a| self.is_qname_attr(a)) {
+            self.add_prefix(&attr.value, known, prefixes);
+        }
+        for child in elem.children.iter() {
+            match child {
+                XmlNode::Elem(id) => self.find_prefixes_recur(&arena[*id], arena, known, prefixes),
+                XmlNode::Text(text) if *self == QNameDetection::Heuristic => {
+                    self.add_prefix(text, known, prefixes)
+                }
+                _ => {}
+            }
+        }
+        known.pop_scope();
+    }
+
+    /// Returns `true` if the value of the attribute is taken as a QName
+    fn is_qname_attr(&self, attr: &XmlAttr) -> bool {
+        match self {
+            QNameDetection::Off => false,
+            QNameDetection::Attributes(names) => {
+                let ns_url = attr.ns.as_ref().map_or("", |ns| &*ns.url);
+                names
+                    .iter()
+                    .any(|(url, local_name)| url == ns_url && *local_name == attr.local_name)
+            }
+            QNameDetection::Heuristic => true,
+        }
+    }
+
+    /// Adds the prefix of the QName in `value`, if it is bound in scope
+    fn add_prefix<'a>(&self, value: &'a str, known: &XmlnsScope, prefixes: &mut BTreeSet<&'a str>) {
+        let value = value.trim_matches([' ', '\t', '\n', '\r']);
+        let prefix = match value.split_once(':') {
+            Some((prefix, local_name)) if is_ncname(prefix) && is_ncname(local_name) => prefix,
+            // Bez prefixu jde o default namespace, heuristika by ale brala kazde slovo
+            None if is_ncname(value) && *self != QNameDetection::Heuristic => "",
+            _ => return,
+        };
+        let bound = known
+            .find_by_prefix(prefix)
+            .is_some_and(|ns| !ns.is_reserved() && !ns.url.is_empty());
+        if bound {
+            prefixes.insert(prefix);
+        }
+    }
+}
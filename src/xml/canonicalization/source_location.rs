@@ -0,0 +1,109 @@
+use std::{collections::HashMap, fmt::Display};
+use xml::{common::TextPosition, name::OwnedName};
+
+/// Place in the XML document where canonicalization failed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    /// Line, counting from 1
+    pub line: u64,
+
+    /// Column, counting from 1
+    pub column: u64,
+
+    /// Path of the element being read, e.g. `/soapenv:Envelope/soapenv:Body/ns:Order[2]`.
+    /// The index is written only for the second and further siblings of the same name.
+    /// `/` outside of the root element.
+    pub path: String,
+}
+
+impl SourceLocation {
+    pub(crate) fn new(position: TextPosition, path: String) -> Self {
+        Self {
+            line: position.row + 1,
+            column: position.column + 1,
+            path,
+        }
+    }
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}, element {}",
+            self.line, self.column, self.path
+        )
+    }
+}
+
+/// Path of the currently open element, maintained while reading the document
+#[derive(Debug, Default)]
+pub(super) struct ElementPath {
+    /// Open elements: qualified name and index among the siblings of the same name (from 1)
+    open: Vec<(String, usize)>,
+
+    /// For the document and every open element: number of children started so far, by name
+    child_counts: Vec<HashMap<String, usize>>,
+}
+
+impl ElementPath {
+    /// Enters a child element of the currently open element
+    pub fn start(&mut self, name: &OwnedName) {
+        let name = qualified_name(name.prefix.as_deref(), &name.local_name);
+        if self.child_counts.is_empty() {
+            self.child_counts.push(HashMap::new());
+        }
+        let count = self
+            .child_counts
+            .last_mut()
+            .unwrap()
+            .entry(name.clone())
+            .or_default();
+        *count += 1;
+        self.open.push((name, *count));
+        self.child_counts.push(HashMap::new());
+    }
+
+    /// Returns the number of open elements
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Leaves the currently open element
+    pub fn end(&mut self) {
+        self.open.pop();
+        self.child_counts.pop();
+    }
+}
+
+impl Display for ElementPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.open.is_empty() {
+            return write!(f, "/");
+        }
+        for (name, index) in self.open.iter() {
+            write_step(f, name, *index)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `prefix:local_name`, or just the local name without a prefix
+pub(super) fn qualified_name(prefix: Option<&str>, local_name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{prefix}:{local_name}"),
+        None => local_name.to_owned(),
+    }
+}
+
+/// Writes one step of an element path
+pub(super) fn write_step(
+    f: &mut impl std::fmt::Write,
+    name: &str,
+    index: usize,
+) -> std::fmt::Result {
+    match index {
+        1 => write!(f, "/{name}"),
+        _ => write!(f, "/{name}[{index}]"),
+    }
+}
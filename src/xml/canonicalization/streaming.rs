@@ -0,0 +1,163 @@
+use super::{
+    C14nSettings, XmlCanonicalizeError,
+    qname_detection::QNameDetection,
+    xml_canonized_doc::{create_reader, new_elem, new_pi},
+};
+use crate::xml::{
+    escape::escape_text,
+    xml_elem::{ExcludedElems, NsScopes, XmlElem, XmlElemArena, XmlNode},
+    xmlns::NsInterner,
+};
+use std::{
+    hash::Hasher,
+    io::{BufReader, Read, Write},
+};
+use xml::reader::XmlEvent;
+
+/// Reads a XML document and writes its canonical form into `writer`, without building the document tree.
+/// Memory use depends on the depth of the document, not on its size.
+/// Only a whole document can be canonicalized this way, use `XmlCanonizedDoc` for subtrees.
+///
+/// The source is buffered internally and has to be UTF-8. The output is written in small pieces,
+/// so the writer should be buffered too (e.g. `BufWriter`), unless it is a hasher.
+/// Reading, writing or the conversion may fail, part of the output may be written by then.
+/// Errors are located at the event being processed.
+/// QName detection is not supported, the prefixes have to be given in the `InclusiveNamespaces PrefixList`.
+pub fn canonicalize_stream<R: Read, W: Write>(
+    reader: R,
+    mut writer: W,
+    settings: &C14nSettings,
+) -> Result<(), XmlCanonicalizeError> {
+    // Prefixy z obsahu by se zjistily az po zapsani start tagu, ktery je ma deklarovat
+    if settings.algorithm.is_exclusive() && settings.qname_detection != QNameDetection::Off {
+        return Err(XmlCanonicalizeError::QNameDetectionNotStreamable);
+    }
+    let mut events = create_reader(
+        BufReader::new(reader),
+        settings,
+        !settings.algorithm.with_comments(),
+    );
+
+    // Otevrene elementy a namespacy v rozsahu vstupu i vystupu
+    let mut elems_stack = Vec::<XmlElem>::new();
+    let mut scopes = NsScopes::default();
+    let mut interner = NsInterner::default();
+    let mut root_found = false;
+    while let Some(e) = events.next() {
+        let e = e?;
+        match e {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                // Scope elementu ve vstupu otevira uz new_elem
+                let elem = new_elem(
+                    name,
+                    &attributes,
+                    namespace,
+                    elems_stack.last(),
+                    &mut scopes.known,
+                    &mut interner,
+                );
+                // Koren dokumentu nema predky, od kterych by dedil xml:* atributy
+                let apex_attrs = elems_stack.is_empty().then_some(&[][..]);
+                let start_tag = elem.get_start_tag(settings, &mut scopes, apex_attrs);
+                write(&mut writer, &start_tag).map_err(|e| events.locate(e))?;
+                elems_stack.push(elem);
+                root_found = true;
+            }
+            XmlEvent::EndElement { .. } => {
+                if let Some(elem) = elems_stack.pop() {
+                    write(&mut writer, &elem.get_end_tag(&mut scopes))
+                        .map_err(|e| events.locate(e))?;
+                }
+            }
+            XmlEvent::Characters(value) => {
+                if elems_stack.is_empty() {
+                    return Err(events.locate(XmlCanonicalizeError::ReadTextValueError));
+                }
+                write(&mut writer, &escape_text(&value)).map_err(|e| events.locate(e))?;
+            }
+            XmlEvent::Comment(text) => {
+                let in_root = !elems_stack.is_empty();
+                write_node(
+                    &mut writer,
+                    &XmlNode::Comment(text),
+                    settings,
+                    in_root,
+                    root_found,
+                )
+                .map_err(|e| events.locate(e))?;
+            }
+            XmlEvent::ProcessingInstruction { name, data } => {
+                let in_root = !elems_stack.is_empty();
+                write_node(
+                    &mut writer,
+                    &new_pi(name, data),
+                    settings,
+                    in_root,
+                    root_found,
+                )
+                .map_err(|e| events.locate(e))?;
+            }
+            _ => {}
+        }
+    }
+
+    if root_found {
+        Ok(())
+    } else {
+        Err(events.locate(XmlCanonicalizeError::EmptyDoc))
+    }
+}
+
+/// Writes a comment or a processing instruction.
+/// Outside of the root element it is separated from the root element by a line feed.
+fn write_node<W: Write>(
+    writer: &mut W,
+    node: &XmlNode,
+    settings: &C14nSettings,
+    in_root: bool,
+    root_found: bool,
+) -> Result<(), XmlCanonicalizeError> {
+    // Komentar ani PI nema potomky, strom elementu neni potreba
+    let node = node.get_canonized_xml(
+        &XmlElemArena::default(),
+        settings,
+        &ExcludedElems::default(),
+        &mut NsScopes::default(),
+    );
+    match (in_root, root_found) {
+        (true, _) => write(writer, &node),
+        (false, false) => {
+            write(writer, &node)?;
+            write(writer, "\n")
+        }
+        (false, true) => {
+            write(writer, "\n")?;
+            write(writer, &node)
+        }
+    }
+}
+
+fn write<W: Write>(writer: &mut W, s: &str) -> Result<(), XmlCanonicalizeError> {
+    writer
+        .write_all(s.as_bytes())
+        .map_err(|e| XmlCanonicalizeError::WriteError(e.to_string()))
+}
+
+/// Adapter which feeds the canonical XML written by `canonicalize_stream` into a `Hasher`
+#[derive(Debug, Clone, Default)]
+pub struct HasherWriter<H>(pub H);
+
+impl<H: Hasher> Write for HasherWriter<H> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
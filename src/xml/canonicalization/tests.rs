@@ -1,12 +1,60 @@
 #[cfg(test)]
 mod tests {
-    use crate::xml::canonicalization::CanonizedXml;
+    use std::hash::{DefaultHasher, Hasher};
 
+    use crate::xml::{
+        canonicalization::{
+            C14nAlgorithm, C14nSettings, CanonizedXml, InclusivePrefixList, WhitespaceMode,
+            XmlCanonicalizeError,
+            digest::{DigestAlgorithm, DigestWriter, digest_stream},
+            parser_limits::ParserLimits,
+            qname_detection::QNameDetection,
+            source_location::SourceLocation,
+            streaming::{HasherWriter, canonicalize_stream},
+            xml_canonized_doc::XmlCanonizedDoc,
+        },
+        inherited_xml_attrs::join_uri_references,
+        xml_elem::{ExcludedElems, NS_DS_URI, XmlElem, XmlNode},
+    };
+
+    /// Canonicalizes with exclusive canonicalization, whitespace-only text nodes are dropped
     fn test_xml(valid_xml_input: &str, expected_output: &str) {
-        let canoni = CanonizedXml::new(valid_xml_input).unwrap();
+        test_xml_with(valid_xml_input, C14nAlgorithm::Exclusive, expected_output)
+    }
+
+    /// Canonicalizes with the given algorithm, whitespace-only text nodes are dropped
+    fn test_xml_with(valid_xml_input: &str, algorithm: C14nAlgorithm, expected_output: &str) {
+        let settings = C14nSettings::new(algorithm).with_whitespace(WhitespaceMode::Normalize);
+        let canoni = CanonizedXml::new_with_settings(valid_xml_input, &settings).unwrap();
         assert_eq!(canoni.as_str(), expected_output)
     }
 
+    /// Canonicalizes the innermost first-child element of the document as the apex of a subtree
+    fn test_innermost_xml_with(
+        valid_xml_input: &str,
+        algorithm: C14nAlgorithm,
+        expected_output: &str,
+    ) {
+        let settings = C14nSettings::new(algorithm).with_whitespace(WhitespaceMode::Normalize);
+        let doc = XmlCanonizedDoc::parse(valid_xml_input, &settings).unwrap();
+        let mut elem: &XmlElem = doc.root();
+        while let Some(child) = elem.children.iter().find_map(|n| match n {
+            XmlNode::Elem(child) => Some(*child),
+            _ => None,
+        }) {
+            elem = &doc.elems()[child];
+        }
+        let mut canoni = String::new();
+        elem.write_canonized(
+            doc.elems(),
+            &settings,
+            &ExcludedElems::default(),
+            &mut canoni,
+        )
+        .unwrap();
+        assert_eq!(canoni, expected_output)
+    }
+
     #[test]
     fn test_default_xmlns() {
         test_xml(
@@ -22,6 +70,81 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_default_xmlns_undeclaration_exclusive() {
+        // xmlns="" se pise, jen kdyz nejblizsi vypsany predek, ktery default namespace
+        // viditelne pouziva, deklaroval neprazdny
+        test_xml(
+            r#"<A xmlns="http://a.a"><B xmlns=""><C xmlns="http://a.a"><D xmlns="" /></C></B></A>"#,
+            r#"<A xmlns="http://a.a"><B xmlns=""><C xmlns="http://a.a"><D xmlns=""></D></C></B></A>"#,
+        );
+        // p:B default namespace nepouziva, undeklaraci dostane az C
+        test_xml(
+            r#"<A xmlns="http://a.a"><p:B xmlns:p="http://p.p" xmlns=""><C /></p:B></A>"#,
+            r#"<A xmlns="http://a.a"><p:B xmlns:p="http://p.p"><C xmlns=""></C></p:B></A>"#,
+        );
+        // p:A default namespace nepouziva, neni co ruseni
+        test_xml(
+            r#"<p:A xmlns:p="http://p.p" xmlns="http://a.a"><B xmlns="" /></p:A>"#,
+            r#"<p:A xmlns:p="http://p.p"><B></B></p:A>"#,
+        );
+        test_xml(r#"<A xmlns=""><B xmlns="" /></A>"#, "<A><B></B></A>");
+
+        // S #default v PrefixList se default namespace ridi pravidly inkluzivni kanonizace
+        let settings = C14nSettings::default()
+            .with_whitespace(WhitespaceMode::Normalize)
+            .with_inclusive_prefixes(InclusivePrefixList::parse("#default"));
+        let canoni = CanonizedXml::new_with_settings(
+            r#"<p:A xmlns:p="http://p.p" xmlns="http://a.a"><p:B xmlns=""><C /></p:B></p:A>"#,
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(
+            canoni.as_str(),
+            r#"<p:A xmlns="http://a.a" xmlns:p="http://p.p"><p:B xmlns=""><C></C></p:B></p:A>"#
+        );
+    }
+
+    #[test]
+    fn test_default_xmlns_undeclaration_inclusive() {
+        // xmlns="" se pise, kdyz rodic ve vystupu ma neprazdny default namespace
+        test_xml_with(
+            r#"<p:A xmlns:p="http://p.p" xmlns="http://a.a"><p:B xmlns=""><C /></p:B></p:A>"#,
+            C14nAlgorithm::Inclusive,
+            r#"<p:A xmlns="http://a.a" xmlns:p="http://p.p"><p:B xmlns=""><C></C></p:B></p:A>"#,
+        );
+        test_xml_with(
+            r#"<A xmlns="http://a.a"><B xmlns=""><C xmlns="http://a.a"><D xmlns="" /></C></B></A>"#,
+            C14nAlgorithm::Inclusive11,
+            r#"<A xmlns="http://a.a"><B xmlns=""><C xmlns="http://a.a"><D xmlns=""></D></C></B></A>"#,
+        );
+        test_xml_with(
+            r#"<A xmlns=""><B xmlns="" /></A>"#,
+            C14nAlgorithm::Inclusive,
+            "<A><B></B></A>",
+        );
+    }
+
+    #[test]
+    fn test_default_xmlns_undeclaration_in_subtree() {
+        // Apex nema ve vystupu predka, xmlns="" na nem nema co rusit
+        let xml = r#"<A xmlns="http://a.a"><B xmlns="" Id="b"><C xmlns="http://a.a"><D xmlns="" /></C></B></A>"#;
+        let doc = XmlCanonizedDoc::parse(xml, &C14nSettings::default()).unwrap();
+        for algorithm in [C14nAlgorithm::Exclusive, C14nAlgorithm::Inclusive] {
+            let settings = C14nSettings::new(algorithm);
+            assert_eq!(
+                doc.canonize_by_id("b", &settings).unwrap().as_str(),
+                r#"<B Id="b"><C xmlns="http://a.a"><D xmlns=""></D></C></B>"#
+            );
+            let mut streamed = Vec::new();
+            canonicalize_stream(xml.as_bytes(), &mut streamed, &settings).unwrap();
+            assert_eq!(
+                String::from_utf8(streamed).unwrap(),
+                doc.canonize(&settings).as_str()
+            );
+        }
+    }
+
     #[test]
     fn test_xmlns_with_prefix() {
         test_xml(
@@ -45,6 +168,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sort_attrs_by_ns_url_code_points() {
+        // Atributy se radi podle namespace URI, ne podle prefixu; velka pismena jsou pred malymi
+        test_xml(
+            r#"<A xmlns:b="http://z.z" xmlns:z="http://a.a" b:x="1" z:y="2" a="1" Z="0" z:Y="3"/>"#,
+            r#"<A xmlns:b="http://z.z" xmlns:z="http://a.a" Z="0" a="1" z:Y="3" z:y="2" b:x="1"></A>"#,
+        );
+    }
+
+    #[test]
+    fn test_sort_xmlns_by_prefix_code_points() {
+        test_xml_with(
+            r#"<A xmlns:a="http://1.1" xmlns:B="http://2.2" xmlns="http://3.3" xmlns:A="http://4.4"/>"#,
+            C14nAlgorithm::Inclusive,
+            r#"<A xmlns="http://3.3" xmlns:A="http://4.4" xmlns:B="http://2.2" xmlns:a="http://1.1"></A>"#,
+        );
+    }
+
     #[test]
     fn test_unused_default_xmlns() {
         // Tady vyzkousim razeni atributu a vypadnuti nepouziteho namespace unused
@@ -124,4 +265,980 @@ mod tests {
             r#"<w:world xmlns:extra="http://example.com/extra" xmlns:w="http://w.w" extra:note="Some extrainfo"><country xmlns="http://default.namespace" capital="Tokyo" continent="Asia" name="Japan" population="125.8 million"><currency>Yen</currency><language primary="true" script="kanji kana">Japanese</language><area>377975</area><emptyTag></emptyTag></country><country xmlns="http://default.namespace" capital="Brasília" continent="South America" name="Brazil" population="213 million"><currency>Real</currency><language primary="true">Portuguese</language><area>8515767</area></country><country xmlns="http://eu.eu" capital="Berlin" continent="Europe" name="Germany" population="83 million"><currency>Euro</currency><language note="used widely" primary="true">German</language><area>357386</area></country></w:world>"#,
         );
     }
+
+    #[test]
+    fn test_prefix_rebound_in_between() {
+        // Prefix da je uprostred predefinovan, vnuk ho musi znovu deklarovat
+        test_xml(
+            r#"<da:A xmlns:da="http://x.x"><da:B xmlns:da="http://y.y"><da:C xmlns:da="http://x.x" /></da:B></da:A>"#,
+            r#"<da:A xmlns:da="http://x.x"><da:B xmlns:da="http://y.y"><da:C xmlns:da="http://x.x"></da:C></da:B></da:A>"#,
+        );
+    }
+
+    #[test]
+    fn test_prefix_rebinding_ends_with_element() {
+        // Predefinovani prefixu plati jen uvnitr B, sourozenec C ma opet puvodni url
+        let xml =
+            r#"<p:A xmlns:p="http://x.x"><p:B xmlns:p="http://y.y"><p:D /></p:B><p:C /></p:A>"#;
+        test_xml(
+            xml,
+            r#"<p:A xmlns:p="http://x.x"><p:B xmlns:p="http://y.y"><p:D></p:D></p:B><p:C></p:C></p:A>"#,
+        );
+        test_xml_with(
+            xml,
+            C14nAlgorithm::Inclusive,
+            r#"<p:A xmlns:p="http://x.x"><p:B xmlns:p="http://y.y"><p:D></p:D></p:B><p:C></p:C></p:A>"#,
+        );
+
+        let mut streamed = Vec::new();
+        canonicalize_stream(xml.as_bytes(), &mut streamed, &C14nSettings::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(streamed).unwrap(),
+            CanonizedXml::new(xml).unwrap().as_str()
+        );
+    }
+
+    #[test]
+    fn test_long_and_non_ascii_prefixes() {
+        let xml = r#"<ns_ExampleCorporationServices1:A xmlns:ns_ExampleCorporationServices1="http://e.e" xmlns:čeština="http://c.c" xmlns:a="http://a.a"><čeština:B a:x="1" čeština:y="2" ns_ExampleCorporationServices1:z="3" /></ns_ExampleCorporationServices1:A>"#;
+        test_xml(
+            xml,
+            r#"<ns_ExampleCorporationServices1:A xmlns:ns_ExampleCorporationServices1="http://e.e"><čeština:B xmlns:a="http://a.a" xmlns:čeština="http://c.c" a:x="1" čeština:y="2" ns_ExampleCorporationServices1:z="3"></čeština:B></ns_ExampleCorporationServices1:A>"#,
+        );
+        // Dlouhe prefixy se radi podle code pointu spolu s kratkymi
+        test_xml_with(
+            xml,
+            C14nAlgorithm::Inclusive,
+            r#"<ns_ExampleCorporationServices1:A xmlns:a="http://a.a" xmlns:ns_ExampleCorporationServices1="http://e.e" xmlns:čeština="http://c.c"><čeština:B a:x="1" čeština:y="2" ns_ExampleCorporationServices1:z="3"></čeština:B></ns_ExampleCorporationServices1:A>"#,
+        );
+        let settings =
+            C14nSettings::default().with_inclusive_prefixes(InclusivePrefixList::parse("čeština"));
+        let canoni = CanonizedXml::new_with_settings(xml, &settings).unwrap();
+        assert!(canoni.as_str().starts_with(
+            r#"<ns_ExampleCorporationServices1:A xmlns:ns_ExampleCorporationServices1="http://e.e" xmlns:čeština="http://c.c">"#
+        ));
+    }
+
+    #[test]
+    fn test_inclusive_renders_all_nss_in_scope() {
+        test_xml_with(
+            r#"
+            <A xmlns="http://def.ault" xmlns:u="http://u.u" xml:lang="cs">
+                <B xmlns:u="http://u.u" />
+                <u:C xmlns:w="http://w.w" />
+            </A>"#,
+            C14nAlgorithm::Inclusive,
+            r#"<A xmlns="http://def.ault" xmlns:u="http://u.u" xml:lang="cs"><B></B><u:C xmlns:w="http://w.w"></u:C></A>"#,
+        );
+    }
+
+    #[test]
+    fn test_inclusive_redefined_and_undeclared_default_xmlns() {
+        test_xml_with(
+            r#"
+            <A xmlns="http://a.a" xmlns:p="http://p.p">
+                <B xmlns="">
+                    <p:C xmlns:p="http://q.q" />
+                </B>
+            </A>"#,
+            C14nAlgorithm::Inclusive,
+            r#"<A xmlns="http://a.a" xmlns:p="http://p.p"><B xmlns=""><p:C xmlns:p="http://q.q"></p:C></B></A>"#,
+        );
+    }
+
+    #[test]
+    fn test_algorithm_uri() {
+        for algorithm in [
+            C14nAlgorithm::Exclusive,
+            C14nAlgorithm::Inclusive,
+            C14nAlgorithm::Inclusive11,
+            C14nAlgorithm::ExclusiveWithComments,
+            C14nAlgorithm::InclusiveWithComments,
+            C14nAlgorithm::Inclusive11WithComments,
+        ] {
+            assert_eq!(C14nAlgorithm::from_uri(algorithm.uri()), Some(algorithm));
+        }
+        assert_eq!(C14nAlgorithm::from_uri("http://unknown"), None);
+    }
+
+    const XML_ATTRS_DOC: &str = r#"
+        <a xmlns:p="http://p.p" xml:base="http://ex.com/x/" xml:lang="en" xml:id="i1" xml:space="preserve">
+            <b xml:base="y/" xml:lang="cs">
+                <p:c xml:base="../z/c.xml" id="c" />
+            </b>
+        </a>"#;
+
+    #[test]
+    fn test_subtree_exclusive_inherits_nothing() {
+        test_innermost_xml_with(
+            XML_ATTRS_DOC,
+            C14nAlgorithm::Exclusive,
+            r#"<p:c xmlns:p="http://p.p" id="c" xml:base="../z/c.xml"></p:c>"#,
+        );
+    }
+
+    #[test]
+    fn test_subtree_inclusive_inherits_xml_attrs() {
+        test_innermost_xml_with(
+            XML_ATTRS_DOC,
+            C14nAlgorithm::Inclusive,
+            r#"<p:c xmlns:p="http://p.p" id="c" xml:base="../z/c.xml" xml:id="i1" xml:lang="cs" xml:space="preserve"></p:c>"#,
+        );
+    }
+
+    #[test]
+    fn test_subtree_inclusive11_fixes_up_xml_base() {
+        test_innermost_xml_with(
+            XML_ATTRS_DOC,
+            C14nAlgorithm::Inclusive11,
+            r#"<p:c xmlns:p="http://p.p" id="c" xml:base="http://ex.com/x/z/c.xml" xml:lang="cs" xml:space="preserve"></p:c>"#,
+        );
+    }
+
+    const ID_DOC: &str = r#"
+        <soapenv:Envelope xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" xmlns:m="http://m.m"
+            xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd">
+            <soapenv:Header><m:H Id="h1" /></soapenv:Header>
+            <soapenv:Body wsu:Id="body" xml:lang="cs">
+                <m:Ping xml:id="ping"><!--c-->1</m:Ping>
+            </soapenv:Body>
+        </soapenv:Envelope>"#;
+
+    #[test]
+    fn test_subtree_by_id() {
+        let settings = C14nSettings::default();
+        let doc = XmlCanonizedDoc::parse(ID_DOC, &settings).unwrap();
+        assert_eq!(
+            doc.canonize_by_id("body", &settings).unwrap().as_str(),
+            "<soapenv:Body xmlns:soapenv=\"http://schemas.xmlsoap.org/soap/envelope/\" xmlns:wsu=\"http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd\" wsu:Id=\"body\" xml:lang=\"cs\">\n                <m:Ping xmlns:m=\"http://m.m\" xml:id=\"ping\">1</m:Ping>\n            </soapenv:Body>"
+        );
+        assert_eq!(
+            doc.canonize_by_id("h1", &settings).unwrap().as_str(),
+            r#"<m:H xmlns:m="http://m.m" Id="h1"></m:H>"#
+        );
+
+        // Tentyz strom lze kanonizovat jinym algoritmem, odkaz "#id" ale komentare nema
+        let settings = C14nSettings::new(C14nAlgorithm::InclusiveWithComments);
+        let ping = r#"<m:Ping xmlns:m="http://m.m" xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd" xml:id="ping" xml:lang="cs">"#;
+        assert_eq!(
+            doc.canonize_by_id("ping", &settings).unwrap().as_str(),
+            format!("{ping}1</m:Ping>")
+        );
+        assert_eq!(
+            CanonizedXml::new_subtree_by_id(ID_DOC, "ping", &settings).unwrap(),
+            doc.canonize_by_id("ping", &settings).unwrap()
+        );
+        assert_eq!(
+            doc.element_by_id("ping")
+                .unwrap()
+                .canonize(&settings)
+                .as_str(),
+            format!("{ping}<!--c-->1</m:Ping>")
+        );
+    }
+
+    #[test]
+    fn test_subtree_by_id_errors() {
+        let settings = C14nSettings::default();
+        assert_eq!(
+            CanonizedXml::new_subtree_by_id(ID_DOC, "nope", &settings),
+            Err(XmlCanonicalizeError::IdNotFound("nope".to_owned()))
+        );
+        // wsu:Id i Id se stejnou hodnotou na ruznych elementech
+        let duplicate = CanonizedXml::new_subtree_by_id(
+            r#"<a xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd"><b Id="x" /><c wsu:Id="x" /></a>"#,
+            "x",
+            &settings,
+        )
+        .unwrap_err();
+        assert_eq!(
+            duplicate.kind(),
+            &XmlCanonicalizeError::DuplicateId("x".to_owned())
+        );
+        assert_eq!(duplicate.location().unwrap().path, "/a/c");
+        // Atribut id bez namespace ani Id v cizim namespace nejsou ID
+        assert_eq!(
+            CanonizedXml::new_subtree_by_id(
+                r#"<a xmlns:f="http://f.f"><b id="x" f:Id="x" /></a>"#,
+                "x",
+                &settings
+            ),
+            Err(XmlCanonicalizeError::IdNotFound("x".to_owned()))
+        );
+    }
+
+    const ENVELOPED_DOC: &str = r##"<?xml version="1.0"?>
+<inv:Invoice xmlns:inv="http://inv.inv" Id="inv1">
+  <inv:Total>100<!--net--></inv:Total>
+  <Signature xmlns="http://www.w3.org/2000/09/xmldsig#">
+    <SignedInfo><Reference URI="" /></SignedInfo>
+    <Object><ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#" /></Object>
+  </Signature>
+</inv:Invoice>
+<!--after-->"##;
+
+    #[test]
+    fn test_enveloped_signature() {
+        let expected = "<inv:Invoice xmlns:inv=\"http://inv.inv\" Id=\"inv1\">\n  <inv:Total>100</inv:Total>\n  \n</inv:Invoice>";
+        let settings = C14nSettings::default();
+        let canoni = CanonizedXml::new_enveloped(ENVELOPED_DOC, "", &settings).unwrap();
+        assert_eq!(canoni.as_str(), expected);
+        let canoni = CanonizedXml::new_enveloped(ENVELOPED_DOC, "#inv1", &settings).unwrap();
+        assert_eq!(canoni.as_str(), expected);
+
+        // Odkaz do tehoz dokumentu komentare nezachovava, ani uvnitr podepsaneho podstromu
+        let settings = C14nSettings::new(C14nAlgorithm::ExclusiveWithComments);
+        let doc = XmlCanonizedDoc::parse(ENVELOPED_DOC, &settings).unwrap();
+        assert_eq!(
+            doc.canonize_enveloped("", &settings).unwrap().as_str(),
+            expected
+        );
+        assert_eq!(
+            doc.canonize_enveloped("#inv1", &settings).unwrap().as_str(),
+            expected
+        );
+        assert!(doc.canonize(&settings).as_str().contains("100<!--net-->"));
+    }
+
+    #[test]
+    fn test_enveloped_signature_errors() {
+        let settings = C14nSettings::default();
+        assert_eq!(
+            CanonizedXml::new_enveloped(ENVELOPED_DOC, "http://ex.com/doc.xml", &settings),
+            Err(XmlCanonicalizeError::UnsupportedReferenceUri(
+                "http://ex.com/doc.xml".to_owned()
+            ))
+        );
+        let not_found = CanonizedXml::new_enveloped("<a><b /></a>", "", &settings).unwrap_err();
+        assert_eq!(not_found.kind(), &XmlCanonicalizeError::SignatureNotFound);
+        assert_eq!(not_found.location().unwrap().path, "/a");
+        // Signature v jinem namespace neni ds:Signature
+        let not_found =
+            CanonizedXml::new_enveloped(r#"<a><b Id="b1"><Signature /></b></a>"#, "#b1", &settings)
+                .unwrap_err();
+        assert_eq!(not_found.kind(), &XmlCanonicalizeError::SignatureNotFound);
+        assert_eq!(not_found.location().unwrap().path, "/a/b");
+        let ambiguous = CanonizedXml::new_enveloped(
+            r#"<a xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:Signature /><ds:Signature /></a>"#,
+            "",
+            &settings,
+        )
+        .unwrap_err();
+        assert_eq!(ambiguous.kind(), &XmlCanonicalizeError::AmbiguousSignature);
+        assert_eq!(ambiguous.location().unwrap().path, "/a/ds:Signature[2]");
+    }
+
+    const TWO_SIGNATURES_DOC: &str = r##"<a xmlns:ds="http://www.w3.org/2000/09/xmldsig#" Id="a1">
+  <b Id="b1">text</b>
+  <ds:Signature Id="s1"><ds:SignedInfo><ds:Reference URI=""/></ds:SignedInfo></ds:Signature>
+  <ds:Signature Id="s2"><ds:SignedInfo><ds:Reference URI="#a1"/></ds:SignedInfo></ds:Signature>
+</a>"##;
+
+    #[test]
+    fn test_enveloped_signature_by_reference() {
+        let settings = C14nSettings::default();
+        let doc = XmlCanonizedDoc::parse(TWO_SIGNATURES_DOC, &settings).unwrap();
+        assert_eq!(
+            doc.canonize_enveloped("", &settings).unwrap_err().kind(),
+            &XmlCanonicalizeError::AmbiguousSignature
+        );
+
+        let ns = [("ds", NS_DS_URI)];
+        let references = doc.select("//ds:Reference", &ns).unwrap();
+        // Vynechava se jen podpis, ktery drzi referenci
+        let first = references[0].canonize_enveloped(&settings).unwrap();
+        assert!(!first.as_str().contains(r#"Id="s1""#));
+        assert!(first.as_str().contains(r#"Id="s2""#));
+        let second = references[1].canonize_enveloped(&settings).unwrap();
+        assert!(second.as_str().contains(r#"Id="s1""#));
+        assert!(!second.as_str().contains(r#"Id="s2""#));
+        assert_eq!(
+            references[1]
+                .digest_enveloped(&settings, DigestAlgorithm::Sha256)
+                .unwrap()
+                .as_ref(),
+            ring::digest::digest(&ring::digest::SHA256, second.as_bytes()).as_ref()
+        );
+
+        // Reference mimo podpis nebo podpis mimo odkazovana data
+        let b = doc.element_by_id("b1").unwrap();
+        let no_uri = b.canonize_enveloped(&settings).unwrap_err();
+        assert_eq!(
+            no_uri.kind(),
+            &XmlCanonicalizeError::UnsupportedReferenceUri(String::new())
+        );
+        assert_eq!(no_uri.location().unwrap().path, "/a/b");
+        let outside = XmlCanonizedDoc::parse(
+            r##"<a xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><b Id="b1"/><ds:Signature><ds:Reference URI="#b1"/></ds:Signature></a>"##,
+            &settings,
+        )
+        .unwrap();
+        let reference = outside.select("//ds:Reference", &ns).unwrap()[0];
+        let not_found = reference.canonize_enveloped(&settings).unwrap_err();
+        assert_eq!(not_found.kind(), &XmlCanonicalizeError::SignatureNotFound);
+        assert_eq!(
+            not_found.location().unwrap().path,
+            "/a/ds:Signature/ds:Reference"
+        );
+        let missing = XmlCanonizedDoc::parse(
+            r##"<a xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:Signature><ds:Reference URI="#x"/></ds:Signature></a>"##,
+            &settings,
+        )
+        .unwrap();
+        let reference = missing.select("//ds:Reference", &ns).unwrap()[0];
+        let not_found = reference.canonize_enveloped(&settings).unwrap_err();
+        assert_eq!(
+            not_found.kind(),
+            &XmlCanonicalizeError::IdNotFound("x".to_owned())
+        );
+        assert_eq!(
+            not_found.location().unwrap().path,
+            "/a/ds:Signature/ds:Reference"
+        );
+    }
+
+    #[test]
+    fn test_inclusive11_whole_doc() {
+        test_xml_with(
+            XML_ATTRS_DOC,
+            C14nAlgorithm::Inclusive11,
+            r#"<a xmlns:p="http://p.p" xml:base="http://ex.com/x/" xml:id="i1" xml:lang="en" xml:space="preserve"><b xml:base="y/" xml:lang="cs"><p:c id="c" xml:base="../z/c.xml"></p:c></b></a>"#,
+        );
+    }
+
+    #[test]
+    fn test_join_uri_references() {
+        assert_eq!(
+            join_uri_references("http://ex.com/x/y/", "../z/c.xml"),
+            "http://ex.com/x/z/c.xml"
+        );
+        assert_eq!(join_uri_references("a/b/", "../../../c/"), "../c/");
+        assert_eq!(join_uri_references("http://ex.com", "d"), "http://ex.com/d");
+        assert_eq!(
+            join_uri_references("http://ex.com/a?q", "//other.org/./b"),
+            "http://other.org/b"
+        );
+        assert_eq!(
+            join_uri_references("http://ex.com/a?q", "#f"),
+            "http://ex.com/a?q#f"
+        );
+    }
+
+    #[test]
+    fn test_exclusive_with_prefix_list() {
+        let prefix_list = InclusivePrefixList::parse(" tns  #default ");
+        assert_eq!(prefix_list.to_string(), "#default tns");
+
+        let canoni = CanonizedXml::new_exclusive_with_prefix_list(
+            r#"<p:A xmlns:p="http://p.p" xmlns:tns="http://t.t" xmlns="http://d.d" xmlns:u="http://u.u"><p:B type="tns:T" xmlns:tns="http://t.t" /><p:C xmlns:tns="http://t2.t2" /></p:A>"#,
+            &prefix_list,
+        )
+        .unwrap();
+        assert_eq!(
+            canoni.as_str(),
+            r#"<p:A xmlns="http://d.d" xmlns:p="http://p.p" xmlns:tns="http://t.t"><p:B type="tns:T"></p:B><p:C xmlns:tns="http://t2.t2"></p:C></p:A>"#
+        );
+    }
+
+    const QNAME_DOC: &str = r#"<A xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:tns="http://t.t" xmlns:o="http://o.o" xmlns:x="http://x.x"><B Id="b" xsi:type="tns:OrderType" ref="x:One"><C>o:Value</C><D xmlns="http://d.d" xsi:type="Local" url="http://o.o" lang="xml:cs">u:unbound</D></B></A>"#;
+
+    #[test]
+    fn test_qname_detection_xsi_type() {
+        let settings = C14nSettings::default().with_qname_detection(QNameDetection::xsi_type());
+        let doc = XmlCanonizedDoc::parse(QNAME_DOC, &settings).unwrap();
+        // Hodnota bez prefixu patri do default namespace
+        assert_eq!(
+            doc.inclusive_prefixes_for("", &settings)
+                .unwrap()
+                .to_string(),
+            "#default tns"
+        );
+        assert_eq!(
+            doc.canonize(&settings).as_str(),
+            r#"<A xmlns:tns="http://t.t"><B xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" Id="b" ref="x:One" xsi:type="tns:OrderType"><C>o:Value</C><D xmlns="http://d.d" lang="xml:cs" url="http://o.o" xsi:type="Local">u:unbound</D></B></A>"#
+        );
+        // Apex deklaruje prefix zdedeny od predka
+        assert!(doc
+            .canonize_by_id("b", &settings)
+            .unwrap()
+            .as_str()
+            .starts_with(r#"<B xmlns:tns="http://t.t" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" Id="b""#));
+
+        // Vlastni seznam atributu a PrefixList z nastaveni
+        let settings = C14nSettings::default()
+            .with_inclusive_prefixes(InclusivePrefixList::parse("o"))
+            .with_qname_detection(QNameDetection::attributes([("", "ref")]));
+        assert_eq!(
+            doc.inclusive_prefixes_for("#b", &settings)
+                .unwrap()
+                .to_string(),
+            "o x"
+        );
+        assert_eq!(
+            doc.inclusive_prefixes_for("#missing", &settings)
+                .unwrap_err(),
+            XmlCanonicalizeError::IdNotFound("missing".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_qname_detection_heuristic() {
+        let settings = C14nSettings::default().with_qname_detection(QNameDetection::Heuristic);
+        let doc = XmlCanonizedDoc::parse(QNAME_DOC, &settings).unwrap();
+        // Bez URL, nenavazanych a rezervovanych prefixu a hodnot bez prefixu
+        assert_eq!(
+            doc.inclusive_prefixes_for("", &settings)
+                .unwrap()
+                .to_string(),
+            "o tns x"
+        );
+        assert!(doc.canonize(&settings).as_str().starts_with(
+            r#"<A xmlns:o="http://o.o" xmlns:tns="http://t.t" xmlns:x="http://x.x"><B "#
+        ));
+
+        // Inkluzivni kanonizace vypisuje vsechny namespacy i bez detekce
+        let inclusive = settings.clone().with_qname_detection(QNameDetection::Off);
+        for algorithm in [C14nAlgorithm::Inclusive, C14nAlgorithm::Inclusive11] {
+            assert_eq!(
+                doc.canonize(
+                    &C14nSettings::new(algorithm).with_qname_detection(QNameDetection::Heuristic)
+                ),
+                doc.canonize(&C14nSettings::new(algorithm))
+            );
+        }
+
+        // Streamovani prefixy predem nezna
+        assert_eq!(
+            canonicalize_stream(QNAME_DOC.as_bytes(), Vec::new(), &settings).unwrap_err(),
+            XmlCanonicalizeError::QNameDetectionNotStreamable
+        );
+        assert!(canonicalize_stream(QNAME_DOC.as_bytes(), Vec::new(), &inclusive).is_ok());
+    }
+
+    const COMMENTS_DOC: &str = r#"<!--before-->
+        <A xmlns:u="http://u.u">
+            <!-- inner -->
+            <u:B />
+        </A>
+        <!--after 1--><!--after 2-->"#;
+
+    #[test]
+    fn test_exclusive_with_comments() {
+        test_xml_with(
+            COMMENTS_DOC,
+            C14nAlgorithm::ExclusiveWithComments,
+            "<!--before-->\n<A><!-- inner --><u:B xmlns:u=\"http://u.u\"></u:B></A>\n<!--after 1-->\n<!--after 2-->",
+        );
+    }
+
+    #[test]
+    fn test_inclusive_with_comments() {
+        test_xml_with(
+            COMMENTS_DOC,
+            C14nAlgorithm::InclusiveWithComments,
+            "<!--before-->\n<A xmlns:u=\"http://u.u\"><!-- inner --><u:B></u:B></A>\n<!--after 1-->\n<!--after 2-->",
+        );
+    }
+
+    #[test]
+    fn test_comments_dropped_without_comments_variant() {
+        test_xml(COMMENTS_DOC, r#"<A><u:B xmlns:u="http://u.u"></u:B></A>"#);
+    }
+
+    #[test]
+    fn test_processing_instructions() {
+        let xml = r#"<?xml version="1.0"?>
+        <?xml-stylesheet   href="doc.xsl"
+           type="text/xsl"   ?>
+        <!--c-->
+        <A><?vendor a</b>/c<?><?empty?>t</A>
+        <?after?>"#;
+        test_xml(
+            xml,
+            "<?xml-stylesheet href=\"doc.xsl\"\n           type=\"text/xsl\"   ?>\n<A><?vendor a</b>/c<?><?empty?>t</A>\n<?after?>",
+        );
+        test_xml_with(
+            xml,
+            C14nAlgorithm::InclusiveWithComments,
+            "<?xml-stylesheet href=\"doc.xsl\"\n           type=\"text/xsl\"   ?>\n<!--c-->\n<A><?vendor a</b>/c<?><?empty?>t</A>\n<?after?>",
+        );
+    }
+
+    #[test]
+    fn test_mixed_content() {
+        test_xml(
+            r#"<p>Hello <b>world</b>!</p>"#,
+            r#"<p>Hello <b>world</b>!</p>"#,
+        );
+    }
+
+    #[test]
+    fn test_mixed_content_with_comments() {
+        let xml = r#"<p>a<!--c-->b<i/>c<i>d</i></p>"#;
+        test_xml(xml, r#"<p>ab<i></i>c<i>d</i></p>"#);
+        test_xml_with(
+            xml,
+            C14nAlgorithm::ExclusiveWithComments,
+            r#"<p>a<!--c-->b<i></i>c<i>d</i></p>"#,
+        );
+    }
+
+    #[test]
+    fn test_escaping() {
+        test_xml(
+            r#"<a attr="x &amp; &lt;y&gt; &quot;q&quot; 'a' &#9;&#10;&#13;">1 &lt; 2 &amp;&amp; 3 &gt; 2 &#13; "q" 'a'<![CDATA[<c> & d]]></a>"#,
+            r#"<a attr="x &amp; &lt;y> &quot;q&quot; 'a' &#x9;&#xA;&#xD;">1 &lt; 2 &amp;&amp; 3 &gt; 2 &#xD; "q" 'a'&lt;c&gt; &amp; d</a>"#,
+        );
+    }
+
+    /// Literal whitespace in attribute values, but not in character references, comments, CDATA or PIs
+    const ATTR_WHITESPACE_DOC: &str = "<r xmlns:p=\"http://p.p\">\n<a b=\"x\ty\nz\" c=\"1\r\n2\" d=\"&#x9;&#xA;&#xD; &#32;\" p:e=\"\t lead\"><!-- c=\"\t\" --><![CDATA[ x=\"\t\" ]]><?pi x=\"\t\"?>t\tx</a>\n</r>";
+
+    /// The expected outputs are written by `xmllint --c14n`, `--exc-c14n` and `--c14n11`
+    #[test]
+    fn test_attribute_value_normalization() {
+        let inner = "<a b=\"x y z\" c=\"1 2\" d=\"&#x9;&#xA;&#xD;  \" p:e=\"  lead\"><!-- c=\"\t\" --> x=\"\t\" <?pi x=\"\t\"?>t\tx</a>";
+        let inclusive = format!("<r xmlns:p=\"http://p.p\">\n{inner}\n</r>");
+        let exclusive = format!(
+            "<r>\n{}\n</r>",
+            inner.replacen("<a ", "<a xmlns:p=\"http://p.p\" ", 1)
+        );
+        for (algorithm, expected) in [
+            (C14nAlgorithm::InclusiveWithComments, &inclusive),
+            (C14nAlgorithm::ExclusiveWithComments, &exclusive),
+            (C14nAlgorithm::Inclusive11WithComments, &inclusive),
+        ] {
+            let settings = C14nSettings::new(algorithm);
+            let canoni = CanonizedXml::new_with_settings(ATTR_WHITESPACE_DOC, &settings).unwrap();
+            assert_eq!(canoni.as_str(), expected);
+        }
+    }
+
+    #[test]
+    fn test_escaping_xmlns_url() {
+        test_xml(
+            r#"<a:A xmlns:a="http://a.a/?x=1&amp;y=&quot;2&quot;" />"#,
+            r#"<a:A xmlns:a="http://a.a/?x=1&amp;y=&quot;2&quot;"></a:A>"#,
+        );
+    }
+
+    #[test]
+    fn test_whitespace_preserved_by_default() {
+        let canoni =
+            CanonizedXml::new("\n<A>\n  <B> x </B>\r\n  <C>  </C>\t<!-- c -->\n</A>\n").unwrap();
+        assert_eq!(canoni.as_str(), "<A>\n  <B> x </B>\n  <C>  </C>\t\n</A>");
+    }
+
+    #[test]
+    fn test_whitespace_normalized() {
+        test_xml(
+            "\n<A>\n  <B> x </B>\r\n  <C>  </C>\t<!-- c -->\n</A>\n",
+            "<A><B> x </B><C></C></A>",
+        );
+    }
+
+    /// Reader which returns one byte per call, so that `\r\n` is split between reads
+    struct OneByteReader<'a>(&'a [u8]);
+
+    impl std::io::Read for OneByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.split_first() {
+                Some((b, rest)) if !buf.is_empty() => {
+                    buf[0] = *b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_streaming_same_as_tree() {
+        let docs = [
+            XML_ATTRS_DOC,
+            ID_DOC,
+            ENVELOPED_DOC,
+            COMMENTS_DOC,
+            ATTR_WHITESPACE_DOC,
+            "<?pi a/b?>\r\n<A xmlns=\"http://a.a\">\r\n<B xmlns=\"\" b=\"&#13;\r\n\"><![CDATA[<&>]]>\r</B></A>\r\n<?pi?>",
+        ];
+        let algorithms = [
+            C14nAlgorithm::Exclusive,
+            C14nAlgorithm::Inclusive,
+            C14nAlgorithm::Inclusive11,
+            C14nAlgorithm::ExclusiveWithComments,
+            C14nAlgorithm::InclusiveWithComments,
+            C14nAlgorithm::Inclusive11WithComments,
+        ];
+        for doc in docs {
+            for algorithm in algorithms {
+                for whitespace in [WhitespaceMode::Preserve, WhitespaceMode::Normalize] {
+                    let settings = C14nSettings::new(algorithm)
+                        .with_whitespace(whitespace)
+                        .with_inclusive_prefixes(InclusivePrefixList::parse("m"));
+                    let expected = CanonizedXml::new_with_settings(doc, &settings).unwrap();
+                    let mut streamed = Vec::new();
+                    canonicalize_stream(OneByteReader(doc.as_bytes()), &mut streamed, &settings)
+                        .unwrap();
+                    assert_eq!(String::from_utf8(streamed).unwrap(), expected.as_str());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_streaming_into_hasher() {
+        let xml = "<a:A xmlns:a=\"http://a.a\" xmlns:b=\"http://b.b\"><a:B>1</a:B></a:A>";
+        let mut hasher = HasherWriter(DefaultHasher::new());
+        canonicalize_stream(xml.as_bytes(), &mut hasher, &C14nSettings::default()).unwrap();
+
+        let mut expected = DefaultHasher::new();
+        expected.write(CanonizedXml::new(xml).unwrap().as_bytes());
+        assert_eq!(hasher.0.finish(), expected.finish());
+    }
+
+    #[test]
+    fn test_streaming_errors() {
+        let settings = C14nSettings::default();
+        assert_eq!(
+            canonicalize_stream("".as_bytes(), Vec::new(), &settings),
+            CanonizedXml::new("").map(|_| ())
+        );
+        assert!(matches!(
+            canonicalize_stream("<a><b></a>".as_bytes(), Vec::new(), &settings)
+                .unwrap_err()
+                .kind(),
+            XmlCanonicalizeError::XmlReadError(_)
+        ));
+        let full: &mut [u8] = &mut [0u8; 4];
+        assert!(matches!(
+            canonicalize_stream("<abcd></abcd>".as_bytes(), full, &settings)
+                .unwrap_err()
+                .kind(),
+            XmlCanonicalizeError::WriteError(_)
+        ));
+    }
+
+    #[test]
+    fn test_parser_limits() {
+        let check = |xml: &str, limits: ParserLimits, expected: XmlCanonicalizeError| {
+            let settings = C14nSettings::default().with_limits(limits);
+            let tree = CanonizedXml::new_with_settings(xml, &settings).unwrap_err();
+            let stream = canonicalize_stream(xml.as_bytes(), Vec::new(), &settings).unwrap_err();
+            assert_eq!(tree.kind(), &expected);
+            assert_eq!(stream, tree);
+        };
+        let limits = ParserLimits::default();
+
+        let deep = format!("{}{}", "<a>".repeat(4), "</a>".repeat(4));
+        check(
+            &deep,
+            limits.with_max_depth(3),
+            XmlCanonicalizeError::TooDeep(3),
+        );
+        check(
+            &deep,
+            limits.with_max_total_bytes(20),
+            XmlCanonicalizeError::TooLarge(20),
+        );
+        check(
+            r#"<a x="1" y="2" z="3"/>"#,
+            limits.with_max_attributes(2),
+            XmlCanonicalizeError::TooManyAttributes(2),
+        );
+        check(
+            r#"<a xmlns="http://a.a" xmlns:b="http://b.b"><c xmlns:c="http://c.c"/></a>"#,
+            limits.with_max_namespaces(2),
+            XmlCanonicalizeError::TooManyNamespaces(2),
+        );
+        check(
+            "<a>12345<![CDATA[678]]></a>",
+            limits.with_max_text_length(7),
+            XmlCanonicalizeError::TextTooLong(7),
+        );
+
+        // Billion laughs, DTD je zakazano uz pred rozbalenim entit
+        let laughs = r#"<?xml version="1.0"?>
+            <!DOCTYPE lolz [
+              <!ENTITY lol "lol">
+              <!ENTITY lol2 "&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;">
+              <!ENTITY lol3 "&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;">
+            ]>
+            <lolz>&lol3;</lolz>"#;
+        check(laughs, limits, XmlCanonicalizeError::DtdNotAllowed);
+        // S povolenym DTD plati limity rozbalovani entit v xml-rs
+        let settings = C14nSettings::default().with_limits(limits.with_dtd_allowed(true));
+        assert!(matches!(
+            CanonizedXml::new_with_settings(laughs, &settings)
+                .unwrap_err()
+                .kind(),
+            XmlCanonicalizeError::XmlReadError(_)
+        ));
+        let small = r#"<!DOCTYPE a [<!ENTITY e "entity">]><a>&e;</a>"#;
+        let canoni = CanonizedXml::new_with_settings(small, &settings).unwrap();
+        assert_eq!(canoni.as_str(), "<a>entity</a>");
+        let nested = r#"<!DOCTYPE a [<!ENTITY e "entity"><!ENTITY f "&e;&e;&e;">]><a>&f;</a>"#;
+        let canoni = CanonizedXml::new_with_settings(nested, &settings).unwrap();
+        assert_eq!(canoni.as_str(), "<a>entityentityentity</a>");
+        let settings = C14nSettings::default().with_limits(
+            limits
+                .with_dtd_allowed(true)
+                .with_max_entity_expansion_length(3),
+        );
+        assert!(matches!(
+            CanonizedXml::new_with_settings(nested, &settings)
+                .unwrap_err()
+                .kind(),
+            XmlCanonicalizeError::XmlReadError(_)
+        ));
+        // DTD se odmitne hned na zacatku, zbytek dokumentu se uz necte
+        let external =
+            r#"<!--c--><!DOCTYPE a [<!ENTITY x SYSTEM "file:///etc/passwd"> not even xml"#;
+        check(external, limits, XmlCanonicalizeError::DtdNotAllowed);
+        let settings = C14nSettings::default().with_limits(limits);
+        let e = CanonizedXml::new_with_settings(external, &settings).unwrap_err();
+        assert_eq!(e.location().unwrap().path, "/");
+
+        // Dokument presne na limitech projde
+        let settings = C14nSettings::default().with_limits(
+            limits
+                .with_max_depth(4)
+                .with_max_total_bytes(deep.len() as u64),
+        );
+        assert_eq!(
+            CanonizedXml::new_with_settings(&deep, &settings)
+                .unwrap()
+                .as_str(),
+            deep
+        );
+        // Prebindovany prefix ani zruseny default namespace nepribyvaji, sourozenci se nescitaji
+        let rebound = r#"<a xmlns="http://a.a" xmlns:b="http://b.b"><c xmlns:b="http://c.c"><d xmlns=""/></c><e xmlns:e="http://e.e"/><f xmlns:f="http://f.f"/></a>"#;
+        let settings = C14nSettings::default().with_limits(limits.with_max_namespaces(3));
+        let canoni = CanonizedXml::new_with_settings(rebound, &settings).unwrap();
+        assert_eq!(
+            canoni.as_str(),
+            r#"<a xmlns="http://a.a"><c><d xmlns=""></d></c><e></e><f></f></a>"#
+        );
+        let settings = C14nSettings::default().with_limits(limits.with_max_namespaces(2));
+        let e = CanonizedXml::new_with_settings(rebound, &settings).unwrap_err();
+        assert_eq!(e.kind(), &XmlCanonicalizeError::TooManyNamespaces(2));
+        assert_eq!(e.location().unwrap().path, "/a/e");
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let xml = "<s:Envelope xmlns:s=\"http://s.s\">\n  <s:Body>\n    <Order/>\n    <Order><toolong:x/></Order>\n  </s:Body>\n</s:Envelope>";
+        let e = CanonizedXml::new(xml).unwrap_err();
+        assert!(matches!(e.kind(), XmlCanonicalizeError::XmlReadError(_)));
+        assert_eq!(
+            e.location(),
+            Some(&SourceLocation {
+                line: 4,
+                column: 22,
+                path: "/s:Envelope/s:Body/Order[2]".to_owned()
+            })
+        );
+        // Pozice je tataz, kterou hlasi xml-rs
+        assert!(e.to_string().starts_with(
+            "Failed to read XML: 4:22 Element toolong:x prefix is unbound at line 4, column 22,"
+        ));
+
+        let attrs = r#"<a><b/><b><p:c xmlns:p="http://c.c" x="1" y="2"/></b></a>"#;
+        let settings =
+            C14nSettings::default().with_limits(ParserLimits::default().with_max_attributes(1));
+        let e = CanonizedXml::new_with_settings(attrs, &settings).unwrap_err();
+        assert_eq!(e.kind(), &XmlCanonicalizeError::TooManyAttributes(1));
+        assert_eq!(
+            e.to_string(),
+            format!("{} at line 1, column 11, element /a/b[2]/p:c", e.kind())
+        );
+        assert_eq!(
+            canonicalize_stream(attrs.as_bytes(), Vec::new(), &settings),
+            Err(e)
+        );
+
+        let e = CanonizedXml::new("<a/>text").unwrap_err();
+        assert_eq!(e.location().unwrap().path, "/");
+    }
+
+    #[test]
+    fn test_from_bytes_encodings() {
+        let settings = C14nSettings::default();
+        let expected = "<a b=\"Šíř\">Žluťoučký kůň</a>";
+        let check = |bytes: &[u8]| {
+            let canoni = CanonizedXml::from_bytes(bytes, &settings).unwrap();
+            assert_eq!(canoni.as_str(), expected);
+        };
+
+        check(expected.as_bytes());
+        check(&[b"\xEF\xBB\xBF", expected.as_bytes()].concat());
+
+        // "Šíř" a "Žluťoučký kůň" v ISO-8859-2 a windows-1250
+        check(
+            b"<?xml version=\"1.0\" encoding=\"ISO-8859-2\"?>\n<a b=\"\xA9\xED\xF8\">\xAElu\xBBou\xE8k\xFD k\xF9\xF2</a>"
+                .as_slice(),
+        );
+        check(
+            b"<?xml version='1.0' encoding='windows-1250'?><a b=\"\x8A\xED\xF8\">\x8Elu\x9Dou\xE8k\xFD k\xF9\xF2</a>"
+                .as_slice(),
+        );
+
+        // ISO-8859-1 neni windows-1252, bajty 0x80-0x9F jsou ridici znaky C1
+        let latin1 = CanonizedXml::from_bytes(
+            b"<?xml version='1.0' encoding='ISO-8859-1'?><a b=\"\x80\">caf\xE9\x9F</a>",
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(latin1.as_str(), "<a b=\"\u{80}\">caf\u{E9}\u{9F}</a>");
+        let windows1252 = CanonizedXml::from_bytes(
+            b"<?xml version='1.0' encoding='windows-1252'?><a b=\"\x80\">caf\xE9\x9F</a>",
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(
+            windows1252.as_str(),
+            "<a b=\"\u{20AC}\">caf\u{E9}\u{178}</a>"
+        );
+
+        let utf16 = format!("<?xml version=\"1.0\" encoding=\"UTF-16\"?>{expected}");
+        let le: Vec<u8> = utf16.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = utf16.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        check(&[b"\xFF\xFE", le.as_slice()].concat());
+        check(&[b"\xFE\xFF", be.as_slice()].concat());
+        check(&le);
+        check(&be);
+
+        // Deklarace v &str se ignoruje, text uz je dekodovany
+        let declared = format!("<?xml version=\"1.0\" encoding=\"ISO-8859-2\"?>{expected}");
+        assert_eq!(CanonizedXml::new(&declared).unwrap().as_str(), expected);
+
+        let canoni = CanonizedXml::from_reader(le.as_slice(), &settings).unwrap();
+        assert_eq!(canoni.as_str(), expected);
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        let settings = C14nSettings::default();
+        assert_eq!(
+            CanonizedXml::from_bytes(
+                b"<?xml version=\"1.0\" encoding=\"x-klingon\"?><a/>",
+                &settings
+            ),
+            Err(XmlCanonicalizeError::UnsupportedEncoding(
+                "x-klingon".to_owned()
+            ))
+        );
+        // UTF-16 deklarovane v dokumentu, ktery UTF-16 neni
+        assert_eq!(
+            CanonizedXml::from_bytes(
+                b"<?xml version=\"1.0\" encoding=\"UTF-16\"?><a/>",
+                &settings
+            ),
+            Err(XmlCanonicalizeError::UnsupportedEncoding(
+                "UTF-16".to_owned()
+            ))
+        );
+        assert_eq!(
+            CanonizedXml::from_bytes(b"<a>\xE8</a>", &settings),
+            Err(XmlCanonicalizeError::MalformedEncoding("UTF-8".to_owned()))
+        );
+
+        let limited = settings.with_limits(ParserLimits::default().with_max_total_bytes(4));
+        assert_eq!(
+            CanonizedXml::from_bytes(b"<abc/>", &limited),
+            Err(XmlCanonicalizeError::TooLarge(4))
+        );
+        assert_eq!(
+            CanonizedXml::from_reader(b"<abc/>".as_slice(), &limited),
+            Err(XmlCanonicalizeError::TooLarge(4))
+        );
+    }
+
+    #[test]
+    fn test_digest_stream() {
+        let xml = "<a:A xmlns:a=\"http://a.a\" xmlns:b=\"http://b.b\">\r\n<a:B>1</a:B></a:A>";
+        let settings = C14nSettings::default();
+        let canoni = CanonizedXml::new(xml).unwrap();
+        for algorithm in [
+            DigestAlgorithm::Sha1,
+            DigestAlgorithm::Sha256,
+            DigestAlgorithm::Sha384,
+            DigestAlgorithm::Sha512,
+        ] {
+            let digest = digest_stream(xml.as_bytes(), &settings, algorithm).unwrap();
+            assert_eq!(digest.as_ref(), canoni.digest(algorithm).as_ref());
+            assert_eq!(DigestAlgorithm::from_uri(algorithm.uri()), Some(algorithm));
+        }
+
+        // Kontext lze predat i s jiz zahashovanymi daty
+        let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+        context.update(b"<prefix>");
+        let mut writer = DigestWriter::from(context);
+        canonicalize_stream(xml.as_bytes(), &mut writer, &settings).unwrap();
+        let expected = ring::digest::digest(
+            &ring::digest::SHA256,
+            format!("<prefix>{canoni}").as_bytes(),
+        );
+        assert_eq!(writer.finish().as_ref(), expected.as_ref());
+    }
+
+    #[test]
+    fn test_digest_of_tree() {
+        let settings = C14nSettings::new(C14nAlgorithm::ExclusiveWithComments);
+        let doc = XmlCanonizedDoc::parse(ENVELOPED_DOC, &settings).unwrap();
+        let sha256 = |canoni: CanonizedXml| {
+            ring::digest::digest(&ring::digest::SHA256, canoni.as_bytes())
+                .as_ref()
+                .to_vec()
+        };
+        let algorithm = DigestAlgorithm::Sha256;
+
+        assert_eq!(
+            doc.digest(&settings, algorithm).as_ref(),
+            sha256(doc.canonize(&settings))
+        );
+        assert_eq!(
+            doc.digest_by_id("inv1", &settings, algorithm)
+                .unwrap()
+                .as_ref(),
+            sha256(doc.canonize_by_id("inv1", &settings).unwrap())
+        );
+        for uri in ["", "#inv1"] {
+            assert_eq!(
+                doc.digest_enveloped(uri, &settings, algorithm)
+                    .unwrap()
+                    .as_ref(),
+                sha256(doc.canonize_enveloped(uri, &settings).unwrap())
+            );
+        }
+        let total = doc.root_element().child_elements().next().unwrap();
+        assert_eq!(
+            total.digest(&settings, algorithm).as_ref(),
+            sha256(total.canonize(&settings))
+        );
+        assert_eq!(
+            doc.digest_by_id("nope", &settings, algorithm).unwrap_err(),
+            XmlCanonicalizeError::IdNotFound("nope".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_doc_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<XmlCanonizedDoc>();
+
+        // Jeden dokument, kanonizace ve vice vlaknech
+        let settings = C14nSettings::default();
+        let doc = XmlCanonizedDoc::parse(ID_DOC, &settings).unwrap();
+        let expected = doc.canonize_by_id("body", &settings).unwrap();
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| doc.canonize_by_id("body", &settings).unwrap()))
+                .collect();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), expected);
+            }
+        });
+    }
 }
@@ -1,50 +1,666 @@
-use super::XmlCanonicalizeError;
-use crate::{
-    tools::str_to_tinystr16,
-    xml::{
-        xml_attr::XmlAttr,
-        xml_elem::{ElemContent, XmlElem, XmlElemRc},
-        xmlns::Xmlns,
-        xmlns_collection::XmlnsCollection,
+use super::{
+    C14nSettings, CanonizedXml, InclusivePrefixList, WhitespaceMode, XmlCanonicalizeError,
+    digest::{DigestAlgorithm, DigestWriter},
+    parser_limits::{LimitedEvents, LimitedRead},
+    qname_detection::QNameDetection,
+    source_location::{SourceLocation, write_step},
+};
+use crate::xml::{
+    dom::{
+        Element,
+        query::{Query, QueryError},
     },
+    xml_attr::XmlAttr,
+    xml_elem::{ElemId, ExcludedElems, NS_DS_URI, NsScopes, XmlElem, XmlElemArena, XmlNode},
+    xmlns::{NsInterner, Xmlns, XmlnsPrefix},
+    xmlns_scope::XmlnsScope,
 };
+use ring::digest::Digest;
 use std::{
-    collections::{BTreeSet, LinkedList},
-    rc::Rc,
+    borrow::Cow,
+    collections::BTreeSet,
+    fmt::{self, Write},
+    io::Read,
+};
+use xml::{
+    Encoding, ParserConfig, attribute::OwnedAttribute, common::TextPosition, name::OwnedName,
+    namespace::Namespace, reader::XmlEvent,
 };
-use tinystr::TinyStr16;
-use xml::{EventReader, name::OwnedName, namespace::Namespace, reader::XmlEvent};
 
-/// A XML document tree ready to be rendered as XML string in its canonical form.
+/// A parsed XML document, ready to be rendered in its canonical form
+/// as a whole, or by subtrees selected by an ID.
+/// The document is immutable and `Send + Sync`, so it can be parsed in one thread
+/// and canonicalized in others.
 #[derive(Debug)]
-pub(crate) struct XmlCanonizedDoc {
-    root: XmlElemRc,
+pub struct XmlCanonizedDoc {
+    /// All elements of the document
+    elems: XmlElemArena,
+
+    /// Nodes preceding the root element (comments and processing instructions)
+    before_root: Vec<XmlNode>,
+
+    root: ElemId,
+
+    /// Nodes following the root element (comments and processing instructions)
+    after_root: Vec<XmlNode>,
 }
 
 impl XmlCanonizedDoc {
     /// Parses an XML string into a `XmlCanonizedDoc`.
+    /// Only the parsing options of the settings are used, the algorithm is chosen when canonicalizing.
+    /// Whitespace-only text nodes are kept unless the whitespace mode is `Normalize`.
+    /// Comments are always kept in the tree, they are only rendered by the `#WithComments` algorithms.
+    /// CDATA sections are replaced by their text content, line endings are normalized to `#xA`.
     /// Returns an error if parsing fails.
-    pub fn parse(xml: &str) -> Result<Self, XmlCanonicalizeError> {
-        let parser = EventReader::from_str(xml);
-        let root = parse_elem(parser)?;
-        Ok(XmlCanonizedDoc { root })
+    pub fn parse(xml: &str, settings: &C14nSettings) -> Result<Self, XmlCanonicalizeError> {
+        let parser = create_reader(xml.as_bytes(), settings, false);
+        parse_doc(parser)
+    }
+
+    /// Creates a document of the elements built in `elems`, without nodes outside of the root element
+    pub(crate) fn from_elems(elems: XmlElemArena, root: ElemId) -> Self {
+        Self {
+            elems,
+            before_root: Vec::new(),
+            root,
+            after_root: Vec::new(),
+        }
+    }
+
+    /// Converts the whole document to its canonical form
+    pub fn canonize(&self, settings: &C14nSettings) -> CanonizedXml {
+        self.canonize_reference(None, settings, &ExcludedElems::default())
+    }
+
+    /// Computes the digest of the canonical form of the whole document, see `canonize`.
+    /// The canonical XML is fed straight into the digest, it is never held in memory as a whole.
+    pub fn digest(&self, settings: &C14nSettings, algorithm: DigestAlgorithm) -> Digest {
+        self.digest_reference(None, settings, &ExcludedElems::default(), algorithm)
+    }
+
+    /// Converts the element with the given `wsu:Id`, `Id` or `xml:id` to its canonical form,
+    /// as a same-document reference `URI="#id"` requires.
+    /// The element is the apex of the output: it declares the namespaces it needs
+    /// from the context of its ancestors (and inherits their `xml:*` attributes in inclusive algorithms).
+    /// Comments are left out even by the `#WithComments` algorithms, a dereferenced node set has none.
+    /// Fails if no element or more than one element has the ID.
+    pub fn canonize_by_id(
+        &self,
+        id: &str,
+        settings: &C14nSettings,
+    ) -> Result<CanonizedXml, XmlCanonicalizeError> {
+        let elem = self.find_by_id(id)?;
+        let settings = settings.without_comments();
+        Ok(self.canonize_subtree(elem, &settings))
+    }
+
+    /// Computes the digest of the canonical form of the element with the given ID,
+    /// without holding the canonical XML in memory. See `canonize_by_id`.
+    pub fn digest_by_id(
+        &self,
+        id: &str,
+        settings: &C14nSettings,
+        algorithm: DigestAlgorithm,
+    ) -> Result<Digest, XmlCanonicalizeError> {
+        let elem = self.find_by_id(id)?;
+        let settings = settings.without_comments();
+        Ok(self.digest_subtree(elem, &settings, algorithm))
+    }
+
+    /// Converts the subtree of `apex` to its canonical form, `apex` is the apex of the output
+    pub(crate) fn canonize_subtree(&self, apex: ElemId, settings: &C14nSettings) -> CanonizedXml {
+        self.canonize_reference(Some(apex), settings, &ExcludedElems::default())
+    }
+
+    /// Computes the digest of the canonical form of the subtree of `apex`
+    pub(crate) fn digest_subtree(
+        &self,
+        apex: ElemId,
+        settings: &C14nSettings,
+        algorithm: DigestAlgorithm,
+    ) -> Digest {
+        self.digest_reference(Some(apex), settings, &ExcludedElems::default(), algorithm)
+    }
+
+    /// Dereferences a same-document reference, applies the enveloped-signature transform
+    /// (`http://www.w3.org/2000/09/xmldsig#enveloped-signature`) and converts the result to its canonical form.
+    /// `uri` is the `URI` attribute of the `ds:Reference`: `""` for the whole document, `#id` for a subtree.
+    /// The `ds:Signature` element inside the referenced node set is left out together with its subtree.
+    /// Comments are left out even by the `#WithComments` algorithms, a dereferenced node set has none.
+    /// Fails if the node set contains no signature or more than one (signatures nested in a signature are not counted).
+    /// A document with more signatures is canonicalized through its `ds:Reference`, see `Element::canonize_enveloped`.
+    pub fn canonize_enveloped(
+        &self,
+        uri: &str,
+        settings: &C14nSettings,
+    ) -> Result<CanonizedXml, XmlCanonicalizeError> {
+        let (apex, excluded) = self.dereference_enveloped(uri)?;
+        let settings = settings.without_comments();
+        Ok(self.canonize_reference(apex, &settings, &excluded))
+    }
+
+    /// Computes the digest of the same-document reference `uri` after the enveloped-signature transform,
+    /// without holding the canonical XML in memory. See `canonize_enveloped`.
+    pub fn digest_enveloped(
+        &self,
+        uri: &str,
+        settings: &C14nSettings,
+        algorithm: DigestAlgorithm,
+    ) -> Result<Digest, XmlCanonicalizeError> {
+        let (apex, excluded) = self.dereference_enveloped(uri)?;
+        let settings = settings.without_comments();
+        Ok(self.digest_reference(apex, &settings, &excluded, algorithm))
+    }
+
+    /// Canonicalizes the `ds:Reference` `reference` after the enveloped-signature transform,
+    /// see `Element::canonize_enveloped`
+    pub(crate) fn canonize_enveloped_reference(
+        &self,
+        reference: ElemId,
+        settings: &C14nSettings,
+    ) -> Result<CanonizedXml, XmlCanonicalizeError> {
+        let (apex, excluded) = self.dereference_enveloped_reference(reference)?;
+        let settings = settings.without_comments();
+        Ok(self.canonize_reference(apex, &settings, &excluded))
+    }
+
+    /// Computes the digest of the `ds:Reference` `reference` after the enveloped-signature transform,
+    /// see `Element::digest_enveloped`
+    pub(crate) fn digest_enveloped_reference(
+        &self,
+        reference: ElemId,
+        settings: &C14nSettings,
+        algorithm: DigestAlgorithm,
+    ) -> Result<Digest, XmlCanonicalizeError> {
+        let (apex, excluded) = self.dereference_enveloped_reference(reference)?;
+        let settings = settings.without_comments();
+        Ok(self.digest_reference(apex, &settings, &excluded, algorithm))
+    }
+
+    /// Returns the root element, the entry point of the read-only DOM
+    pub fn root_element(&self) -> Element<'_> {
+        Element::new(self, self.root)
+    }
+
+    /// Selects elements of the document by a query, see `Query`.
+    /// `namespaces` bind the prefixes used in the query to namespace URIs.
+    pub fn select(
+        &self,
+        expr: &str,
+        namespaces: &[(&str, &str)],
+    ) -> Result<Vec<Element<'_>>, QueryError> {
+        Query::parse(expr, namespaces)?.select(self)
+    }
+
+    /// Returns the only element with the given `wsu:Id`, `Id` or `xml:id`.
+    /// Fails if no element or more than one element has the ID.
+    pub fn element_by_id(&self, id: &str) -> Result<Element<'_>, XmlCanonicalizeError> {
+        Ok(Element::new(self, self.find_by_id(id)?))
+    }
+
+    /// Returns the `InclusiveNamespaces PrefixList` of exclusive canonicalization of the same-document
+    /// reference `uri` (`""` or `#id`): the list of the settings together with the prefixes
+    /// detected in QName-valued content of the referenced subtree (see `C14nSettings::with_qname_detection`).
+    /// A signer sends this list in the `ds:Transform`, so that the verifier renders the same namespaces.
+    pub fn inclusive_prefixes_for(
+        &self,
+        uri: &str,
+        settings: &C14nSettings,
+    ) -> Result<InclusivePrefixList, XmlCanonicalizeError> {
+        let apex = self.dereference(uri)?.unwrap_or(self.root);
+        let detected = settings
+            .qname_detection
+            .find_prefixes(&self.elems[apex], &self.elems);
+        Ok(settings.inclusive_prefixes.union(&detected))
+    }
+
+    /// Returns the settings for canonicalizing the subtree of `apex`. Exclusive algorithms with
+    /// QName detection get the detected prefixes added to their `InclusiveNamespaces PrefixList`.
+    fn with_detected_prefixes<'a>(
+        &self,
+        apex: ElemId,
+        settings: &'a C14nSettings,
+    ) -> Cow<'a, C14nSettings> {
+        if !settings.algorithm.is_exclusive() || settings.qname_detection == QNameDetection::Off {
+            return Cow::Borrowed(settings);
+        }
+        let detected = settings
+            .qname_detection
+            .find_prefixes(&self.elems[apex], &self.elems);
+        let prefix_list = settings.inclusive_prefixes.union(&detected);
+        Cow::Owned(settings.clone().with_inclusive_prefixes(prefix_list))
+    }
+
+    /// Dereferences a same-document reference: `None` for the whole document (`""`),
+    /// the element with the ID for `#id`
+    fn dereference(&self, uri: &str) -> Result<Option<ElemId>, XmlCanonicalizeError> {
+        match uri {
+            "" => Ok(None),
+            _ => match uri.strip_prefix('#') {
+                Some(id) => Ok(Some(self.find_by_id(id)?)),
+                None => Err(XmlCanonicalizeError::UnsupportedReferenceUri(
+                    uri.to_owned(),
+                )),
+            },
+        }
+    }
+
+    /// Dereferences a same-document reference and finds the only `ds:Signature` in the node set,
+    /// which the enveloped-signature transform leaves out
+    fn dereference_enveloped(
+        &self,
+        uri: &str,
+    ) -> Result<(Option<ElemId>, ExcludedElems), XmlCanonicalizeError> {
+        let apex = self.dereference(uri)?;
+        let signatures = self.find_elems(apex.unwrap_or(self.root), XmlElem::is_signature, false);
+        if signatures.is_empty() {
+            let apex = self.location_of(apex.unwrap_or(self.root));
+            return Err(XmlCanonicalizeError::SignatureNotFound.at(apex));
+        }
+        if signatures.len() > 1 {
+            let second = self.location_of(signatures[1]);
+            return Err(XmlCanonicalizeError::AmbiguousSignature.at(second));
+        }
+        Ok((apex, ExcludedElems::new(signatures)))
+    }
+
+    /// Dereferences the `URI` attribute of a `ds:Reference` and finds the `ds:Signature` holding the reference,
+    /// which the enveloped-signature transform leaves out. Other signatures in the node set are kept.
+    pub(crate) fn dereference_enveloped_reference(
+        &self,
+        reference: ElemId,
+    ) -> Result<(Option<ElemId>, ExcludedElems), XmlCanonicalizeError> {
+        let location = || self.location_of(reference);
+        let reference = Element::new(self, reference);
+        // Bez atributu URI urcuje data aplikace, to neumime
+        let uri = reference.attribute("", "URI").ok_or_else(|| {
+            XmlCanonicalizeError::UnsupportedReferenceUri(String::new()).at(location())
+        })?;
+        let apex = self.dereference(uri).map_err(|e| e.at(location()))?;
+        let signature = reference
+            .ancestors()
+            .find(|e| e.is(NS_DS_URI, "Signature"))
+            .ok_or_else(|| XmlCanonicalizeError::SignatureNotFound.at(location()))?;
+        // Podpis mimo odkazovana data transformace nema co vynechat
+        if let Some(apex) = apex {
+            let apex = Element::new(self, apex);
+            if signature != apex && !signature.ancestors().any(|e| e == apex) {
+                return Err(XmlCanonicalizeError::SignatureNotFound.at(location()));
+            }
+        }
+        Ok((apex, ExcludedElems::new([signature.id()])))
+    }
+
+    /// Finds the only element with the given ID.
+    /// A duplicate ID is an error, otherwise a signature could be moved to a different element
+    /// (signature wrapping attack).
+    fn find_by_id(&self, id: &str) -> Result<ElemId, XmlCanonicalizeError> {
+        let found = self.find_elems(self.root, |e| e.has_id(id), true);
+        match found.len() {
+            0 => Err(XmlCanonicalizeError::IdNotFound(id.to_owned())),
+            1 => Ok(found[0]),
+            _ => {
+                let second = self.location_of(found[1]);
+                Err(XmlCanonicalizeError::DuplicateId(id.to_owned()).at(second))
+            }
+        }
+    }
+
+    /// Returns the position and path of the element, for error reports
+    fn location_of(&self, id: ElemId) -> SourceLocation {
+        let mut steps = Vec::new();
+        let mut current = Some(id);
+        while let Some(step) = current {
+            let elem = &self.elems[step];
+            let name = elem.get_fullname();
+            // Poradi mezi sourozenci stejneho jmena
+            let index = match elem.parent {
+                Some(parent) => {
+                    child_elems(&self.elems[parent])
+                        .take_while(|sibling| *sibling != step)
+                        .filter(|sibling| self.elems[*sibling].get_fullname() == name)
+                        .count()
+                        + 1
+                }
+                None => 1,
+            };
+            steps.push((name, index));
+            current = elem.parent;
+        }
+
+        let mut path = String::new();
+        for (name, index) in steps.iter().rev() {
+            let _ = write_step(&mut path, name, *index);
+        }
+        SourceLocation::new(self.elems[id].position, path)
+    }
+
+    /// Converts a dereferenced node set to its canonical form, see `write_reference`
+    fn canonize_reference(
+        &self,
+        apex: Option<ElemId>,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+    ) -> CanonizedXml {
+        let mut xml = String::new();
+        // Zapis do String nemuze selhat
+        let _ = self.write_reference(apex, settings, excluded, &mut xml);
+        CanonizedXml(xml)
+    }
+
+    /// Computes the digest of a dereferenced node set, the canonical XML goes straight into the digest
+    fn digest_reference(
+        &self,
+        apex: Option<ElemId>,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+        algorithm: DigestAlgorithm,
+    ) -> Digest {
+        let mut writer = DigestWriter::new(algorithm);
+        // Zapis do digestu nemuze selhat
+        let _ = self.write_reference(apex, settings, excluded, &mut writer);
+        writer.finish()
+    }
+
+    /// Writes the canonical form of a dereferenced node set:
+    /// the whole document for `None`, the subtree of the apex otherwise
+    fn write_reference<W: Write>(
+        &self,
+        apex: Option<ElemId>,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+        out: &mut W,
+    ) -> fmt::Result {
+        let settings = self.with_detected_prefixes(apex.unwrap_or(self.root), settings);
+        match apex {
+            None => self.write_xml(&settings, excluded, out),
+            Some(apex) => self.elems[apex].write_canonized(&self.elems, &settings, excluded, out),
+        }
     }
 
-    /// Returns the canonicalized XML as a string.
+    /// Writes the canonicalized XML of the whole document.
     /// Canonicalization is performed recursively using the internal tree structure.
-    pub fn write_xml_as_string(&self) -> String {
-        self.root
-            .borrow_elem_mut()
-            .get_canonized_xml_recur(BTreeSet::default())
+    /// Comments and processing instructions outside of the root element are separated from it by a line feed.
+    fn write_xml<W: Write>(
+        &self,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+        out: &mut W,
+    ) -> fmt::Result {
+        for n in self.before_root.iter() {
+            let node =
+                n.get_canonized_xml(&self.elems, settings, excluded, &mut NsScopes::default());
+            if !node.is_empty() {
+                out.write_str(&node)?;
+                out.write_char('\n')?;
+            }
+        }
+        self.elems[self.root].write_canonized(&self.elems, settings, excluded, out)?;
+        for n in self.after_root.iter() {
+            let node =
+                n.get_canonized_xml(&self.elems, settings, excluded, &mut NsScopes::default());
+            if !node.is_empty() {
+                out.write_char('\n')?;
+                out.write_str(&node)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the elements of the subtree (`apex` included) which match the predicate, in document order.
+    /// The subtrees of the matching elements are only searched if `search_in_matches` is set.
+    fn find_elems(
+        &self,
+        apex: ElemId,
+        is_match: impl Fn(&XmlElem) -> bool,
+        search_in_matches: bool,
+    ) -> Vec<ElemId> {
+        let mut found = Vec::new();
+        let mut stack = vec![apex];
+        while let Some(id) = stack.pop() {
+            let e = &self.elems[id];
+            if is_match(e) {
+                found.push(id);
+                if !search_in_matches {
+                    continue;
+                }
+            }
+            stack.extend(child_elems(e));
+        }
+        // Zasobnik prochazi dokument pozpatku, arena je v poradi dokumentu
+        found.sort();
+        found
+    }
+
+    /// Returns the root element of the document
+    #[cfg(test)]
+    pub(crate) fn root(&self) -> &XmlElem {
+        &self.elems[self.root]
+    }
+
+    /// Returns all elements of the document
+    pub(crate) fn elems(&self) -> &XmlElemArena {
+        &self.elems
+    }
+}
+
+/// Iterates over the child elements of the element
+fn child_elems(elem: &XmlElem) -> impl Iterator<Item = ElemId> + '_ {
+    elem.children.iter().filter_map(|n| match n {
+        XmlNode::Elem(child) => Some(*child),
+        _ => None,
+    })
+}
+
+/// Creates the `xml-rs` reader used for canonicalization, checked against the parser limits of the settings.
+/// Whitespace-only text nodes are reported unless the whitespace mode is `Normalize`,
+/// CDATA sections are reported as text, line endings are normalized.
+/// The source is always read as UTF-8, the `encoding` of the XML declaration is ignored
+/// (documents in other encodings are decoded before, see `CanonizedXml::from_bytes`).
+pub(super) fn create_reader<R: Read>(
+    source: R,
+    settings: &C14nSettings,
+    ignore_comments: bool,
+) -> LimitedEvents<R> {
+    let limits = &settings.limits;
+    let source = LimitedRead::new(source, limits.max_total_bytes());
+    let reader = ParserConfig::new()
+        .ignore_comments(ignore_comments)
+        .whitespace_to_characters(settings.whitespace == WhitespaceMode::Preserve)
+        .cdata_to_characters(true)
+        .override_encoding(Some(Encoding::Utf8))
+        .ignore_invalid_encoding_declarations(true)
+        // O jeden atribut vic, aby prekroceni limitu ohlasil LimitedEvents presnou chybou
+        .max_attributes(limits.max_attributes().saturating_add(1))
+        .max_entity_expansion_length(limits.max_entity_expansion_length())
+        .create_reader(NormalizedInput::new(source, !limits.is_dtd_allowed()));
+    LimitedEvents::new(reader, settings.limits)
+}
+
+/// Reader which normalizes the input as every XML processor has to do before parsing, `xml-rs` doesn't do it:
+/// - `#xD#xA` and lone `#xD` are replaced by `#xA` (end-of-line handling),
+/// - a tab or a line feed written in an attribute value is replaced by a space (attribute-value normalization).
+///
+/// Character references (`&#13;`, `&#9;`) are not affected, the parser resolves them later.
+/// If document type declarations are not allowed, the input ends at the start of one,
+/// so the parser never reads its entities. `LimitedEvents` then reports the rejected DTD.
+/// Works on bytes, so the source has to be UTF-8 (or another ASCII compatible encoding).
+pub(super) struct NormalizedInput<R> {
+    inner: R,
+
+    /// The last byte read was `#xD`, a following `#xA` is skipped
+    after_cr: bool,
+
+    /// Markup the last byte belongs to
+    markup: Markup,
+
+    /// The last two bytes, they end comments, CDATA sections and processing instructions
+    last: [u8; 2],
+
+    reject_doctype: bool,
+
+    /// The input was ended at a rejected document type declaration
+    doctype_rejected: bool,
+}
+
+impl<R> NormalizedInput<R> {
+    pub fn new(inner: R, reject_doctype: bool) -> Self {
+        Self {
+            inner,
+            after_cr: false,
+            markup: Markup::Text,
+            last: [0; 2],
+            reject_doctype,
+            doctype_rejected: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn is_doctype_rejected(&self) -> bool {
+        self.doctype_rejected
     }
 }
 
-/// Reads the next xml object and converts it to a XmlElemRc. The conversion may fail.
-/// It only reads startElement, endElement and text values. Comments are ignored.
-fn parse_elem(parser: EventReader<&[u8]>) -> Result<XmlElemRc, XmlCanonicalizeError> {
-    let mut elems_stack = LinkedList::<XmlElemRc>::new();
-    let mut root: Option<XmlElemRc> = None;
-    for e in parser {
+impl<R: Read> Read for NormalizedInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.doctype_rejected {
+                return Ok(0);
+            }
+            let n = self.inner.read(buf)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            // Vystup neni nikdy delsi nez vstup, takze se da prepisovat na miste
+            let mut len = 0;
+            for i in 0..n {
+                let b = buf[i];
+                let after_cr = std::mem::replace(&mut self.after_cr, b == b'\r');
+                if b == b'\n' && after_cr {
+                    continue;
+                }
+                let b = if b == b'\r' { b'\n' } else { b };
+                self.markup = self.markup.next(b, self.last);
+                if self.reject_doctype && matches!(self.markup, Markup::Doctype { .. }) {
+                    // Zbytek bloku se zahodi, dalsi cteni uz vrati konec vstupu
+                    self.doctype_rejected = true;
+                    return Ok(len);
+                }
+                self.last = [self.last[1], b];
+                buf[len] = match (self.markup, b) {
+                    (Markup::AttrValue(_), b'\t' | b'\n') => b' ',
+                    _ => b,
+                };
+                len += 1;
+            }
+            // Nula by znamenala konec vstupu, precte se tedy dalsi blok
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+/// Markup of the input, as far as it is needed to recognize attribute values.
+/// The input is expected to be well-formed, the parser reports the errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Markup {
+    Text,
+
+    /// Right after `<`
+    TagStart,
+
+    /// Inside a start tag or an end tag
+    Tag,
+
+    /// Inside an attribute value delimited by the quote
+    AttrValue(u8),
+
+    /// Right after `<!`
+    Declaration,
+
+    Comment,
+
+    CData,
+
+    ProcessingInstruction,
+
+    /// Inside the document type declaration: in a quoted literal,
+    /// and in how many `[` brackets of the internal subset
+    Doctype {
+        quote: Option<u8>,
+        depth: usize,
+    },
+}
+
+impl Markup {
+    /// Returns the markup of the byte `b`, `last` are the two bytes before it
+    fn next(self, b: u8, last: [u8; 2]) -> Self {
+        match (self, b) {
+            (Markup::Text, b'<') => Markup::TagStart,
+            (Markup::TagStart, b'!') => Markup::Declaration,
+            (Markup::TagStart, b'?') => Markup::ProcessingInstruction,
+            (Markup::TagStart | Markup::Tag, b'>') => Markup::Text,
+            (Markup::TagStart | Markup::Tag, b'"' | b'\'') => Markup::AttrValue(b),
+            (Markup::TagStart, _) => Markup::Tag,
+            (Markup::AttrValue(quote), _) if b == quote => Markup::Tag,
+            (Markup::Declaration, b'-') => Markup::Comment,
+            (Markup::Declaration, b'[') => Markup::CData,
+            (Markup::Declaration, _) => Markup::Doctype {
+                quote: None,
+                depth: 0,
+            },
+            (Markup::Comment, b'>') if last == *b"--" => Markup::Text,
+            (Markup::CData, b'>') if last == *b"]]" => Markup::Text,
+            (Markup::ProcessingInstruction, b'>') if last[1] == b'?' => Markup::Text,
+            (
+                Markup::Doctype {
+                    quote: Some(q),
+                    depth,
+                },
+                _,
+            ) if b == q => Markup::Doctype { quote: None, depth },
+            (Markup::Doctype { quote: None, depth }, b'"' | b'\'') => Markup::Doctype {
+                quote: Some(b),
+                depth,
+            },
+            (Markup::Doctype { quote: None, depth }, b'[') => Markup::Doctype {
+                quote: None,
+                depth: depth + 1,
+            },
+            (Markup::Doctype { quote: None, depth }, b']') => Markup::Doctype {
+                quote: None,
+                depth: depth.saturating_sub(1),
+            },
+            (
+                Markup::Doctype {
+                    quote: None,
+                    depth: 0,
+                },
+                b'>',
+            ) => Markup::Text,
+            (markup, _) => markup,
+        }
+    }
+}
+
+/// Reads the XML document and converts it to a XmlCanonizedDoc. The conversion may fail.
+/// It only reads startElement, endElement, text values, comments and processing instructions.
+/// Errors are located at the event which caused them.
+fn parse_doc<R: Read>(
+    mut events: LimitedEvents<R>,
+) -> Result<XmlCanonizedDoc, XmlCanonicalizeError> {
+    let mut elems = XmlElemArena::default();
+    let mut interner = NsInterner::default();
+    let mut known_nss = XmlnsScope::default();
+    let mut elems_stack = Vec::<ElemId>::new();
+    let mut root: Option<ElemId> = None;
+    let mut before_root = Vec::<XmlNode>::new();
+    let mut after_root = Vec::<XmlNode>::new();
+    while let Some(e) = events.next() {
         let e = e?;
         match e {
             XmlEvent::StartElement {
@@ -52,107 +668,166 @@ fn parse_elem(parser: EventReader<&[u8]>) -> Result<XmlElemRc, XmlCanonicalizeEr
                 attributes,
                 namespace,
             } => {
-                let parent = elems_stack.back();
-                // Každý element zná namespacy, které zná jeho rodič. Může přidat vlastní, nebo redefinovat
-                let mut known_nss = match parent {
-                    Some(p) => p.borrow_elem().known_nss.clone(),
-                    None => Default::default(),
-                };
-                insert_known_namespaces(namespace, &mut known_nss)?;
-                let mut used_nss = BTreeSet::<Xmlns>::default();
-
-                // Pokud je použit default namespace (tj. element je definován bez prefixu),
-                // je potřeba navýšit počet použití u default xmlns
-                if name.prefix.is_none() {
-                    if let Some(defaultns) = known_nss.iter().find(|ns| ns.is_default()) {
-                        used_nss
-                            .insert(Xmlns::new(defaultns.url.clone(), defaultns.prefix.clone()));
-                    }
-                }
-
-                let mut attrs = BTreeSet::<XmlAttr>::default();
-                for at in attributes.iter() {
-                    attrs.insert(XmlAttr {
-                        local_name: at.name.local_name.clone(),
-                        value: at.value.clone(),
-                        ns: to_ns_insert_into_used(&at.name, &mut known_nss, &mut used_nss)?,
-                    });
-                }
-
-                let elem = XmlElemRc::new(XmlElem {
-                    ns: to_ns_insert_into_used(&name, &mut known_nss, &mut used_nss)?,
-                    local_name: name.local_name,
-                    used_nss,
-                    content: ElemContent::Text("".to_owned()), // Může být doplněno později
-                    known_nss,
-                    attrs,
-                });
+                let parent = elems_stack.last().copied();
+                let mut elem = new_elem(
+                    name,
+                    &attributes,
+                    namespace,
+                    parent.map(|p| &elems[p]),
+                    &mut known_nss,
+                    &mut interner,
+                );
+                elem.parent = parent;
+                elem.position = events.position();
+                let id = elems.push(elem);
 
                 // elem muze byt neci potomek
-                if let Some(parent_elem) = elems_stack.back() {
-                    parent_elem.borrow_elem_mut().add_child(elem.clone());
-                } else {
-                    root = Some(elem.clone());
+                match parent {
+                    Some(parent) => elems.get_mut(parent).add_child(XmlNode::Elem(id)),
+                    None => root = Some(id),
                 }
-                elems_stack.push_back(elem);
+                elems_stack.push(id);
             }
             XmlEvent::EndElement { .. } => {
-                elems_stack.pop_back();
+                elems_stack.pop();
+                known_nss.pop_scope();
             }
             XmlEvent::Characters(value) => {
-                elems_stack
-                    .back()
-                    .ok_or(XmlCanonicalizeError::ReadTextValueError)?
-                    .borrow_elem_mut()
-                    .set_text_value(value);
+                let parent = elems_stack
+                    .last()
+                    .ok_or_else(|| events.locate(XmlCanonicalizeError::ReadTextValueError))?;
+                elems.get_mut(*parent).add_text(value);
+            }
+            XmlEvent::Comment(text) => {
+                let comment = XmlNode::Comment(text);
+                match (elems_stack.last(), root) {
+                    (Some(parent), _) => elems.get_mut(*parent).add_child(comment),
+                    (None, None) => before_root.push(comment),
+                    (None, Some(_)) => after_root.push(comment),
+                }
+            }
+            XmlEvent::ProcessingInstruction { name, data } => {
+                // PI se zarazuje stejne jako komentar
+                let pi = new_pi(name, data);
+                match (elems_stack.last(), root) {
+                    (Some(parent), _) => elems.get_mut(*parent).add_child(pi),
+                    (None, None) => before_root.push(pi),
+                    (None, Some(_)) => after_root.push(pi),
+                }
             }
             _ => {}
         }
     }
 
     match root {
-        Some(root) => Ok(root),
-        None => Err(XmlCanonicalizeError::EmptyDoc),
+        Some(root) => Ok(XmlCanonizedDoc {
+            elems,
+            before_root,
+            root,
+            after_root,
+        }),
+        None => Err(events.locate(XmlCanonicalizeError::EmptyDoc)),
+    }
+}
+
+/// Creates an element from the start element event.
+/// The element inherits the `xml:*` attributes of its parent.
+/// The scope of the element is opened in `known_nss` (the namespaces in scope at its parent),
+/// the caller closes it at the end element. Namespace URLs and long prefixes are shared through the interner.
+pub(super) fn new_elem(
+    name: OwnedName,
+    attributes: &[OwnedAttribute],
+    namespace: Namespace,
+    parent: Option<&XmlElem>,
+    known_nss: &mut XmlnsScope,
+    interner: &mut NsInterner,
+) -> XmlElem {
+    // Dedi xml:* atributy rodice a jeho predku
+    let inherited_xml_attrs = match parent {
+        Some(p) => p.inherited_xml_attrs.for_children(p.attrs.iter()),
+        None => Default::default(),
+    };
+    known_nss.push_scope();
+    let declared_nss = declare_namespaces(namespace, known_nss, interner);
+    let mut used_nss = BTreeSet::<Xmlns>::default();
+
+    // Pokud je použit default namespace (tj. element je definován bez prefixu),
+    // je potřeba navýšit počet použití u default xmlns
+    if name.prefix.is_none()
+        && let Some(defaultns) = known_nss.find_by_prefix("")
+    {
+        used_nss.insert(defaultns.clone());
+    }
+
+    let mut attrs = BTreeSet::<XmlAttr>::default();
+    for at in attributes.iter() {
+        attrs.insert(XmlAttr {
+            local_name: at.name.local_name.clone(),
+            value: at.value.clone(),
+            ns: to_ns_insert_into_used(&at.name, known_nss, &mut used_nss, interner),
+        });
+    }
+
+    XmlElem {
+        ns: to_ns_insert_into_used(&name, known_nss, &mut used_nss, interner),
+        local_name: name.local_name,
+        used_nss,
+        children: Vec::new(), // Může být doplněno později
+        parent: None,         // Id rodice zna jen arena
+        position: TextPosition::new(),
+        declared_nss,
+        attrs,
+        inherited_xml_attrs,
     }
 }
 
-/// Iterates over a set of namespaces and inserts each into the known list,
-/// following the inserting rules of `insert_or_replace_if_not_found`.
-/// Returns an error if any prefix fails to parse.
-fn insert_known_namespaces(
+/// Creates a processing instruction node from the event data
+pub(super) fn new_pi(target: String, data: Option<String>) -> XmlNode {
+    XmlNode::ProcessingInstruction {
+        target,
+        data: data.as_deref().map(fix_pi_data).unwrap_or_default(),
+    }
+}
+
+/// Returns the data of a processing instruction as defined by XML: the text after the whitespace
+/// which follows the PI target. `xml-rs` 0.8 keeps all whitespace but the first one, it is trimmed here.
+fn fix_pi_data(data: &str) -> String {
+    data.trim_start_matches([' ', '\t', '\n', '\r']).to_owned()
+}
+
+/// Declares the namespaces declared by the element (as reported by `LimitedEvents`) in the current scope
+/// of `known_nss`, unless they are already known there. Returns the declared namespaces.
+fn declare_namespaces(
     namespaces: Namespace,
-    known_nss: &mut XmlnsCollection,
-) -> Result<(), XmlCanonicalizeError> {
+    known_nss: &mut XmlnsScope,
+    interner: &mut NsInterner,
+) -> Vec<Xmlns> {
+    let mut declared = Vec::new();
     for (prefix, url) in namespaces.iter() {
-        let prefix = parse_xmlns_prefix(prefix)?;
-        let xmlns = Xmlns::new(Rc::new(url.to_owned()), prefix);
-        known_nss.insert_or_replace_if_not_found(xmlns);
+        if known_nss.find_by_prefix(prefix).map(|ns| &*ns.url) == Some(url) {
+            continue;
+        }
+        let xmlns = Xmlns::new(interner.intern(url), XmlnsPrefix::new(prefix, interner));
+        known_nss.declare(xmlns.clone());
+        declared.push(xmlns);
     }
-    Ok(())
+    declared
 }
 
-/// Converts an `OwnedName` into an `Xmlns`, inserting it into both the known and used namespace sets.
-/// Returns the created `Xmlns`, or an error if the prefix cannot be parsed.
+/// Converts the prefix of an `OwnedName` into an `Xmlns` and inserts it into the used namespace set.
+/// The namespace in scope is reused, the reader has already resolved the prefix against it.
 fn to_ns_insert_into_used(
     n: &OwnedName,
-    known_nss: &mut XmlnsCollection,
+    known_nss: &XmlnsScope,
     used_nss: &mut BTreeSet<Xmlns>,
-) -> Result<Option<Xmlns>, XmlCanonicalizeError> {
-    match &n.prefix {
-        Some(prefix) => {
-            let prefix = parse_xmlns_prefix(prefix)?;
-            let url = n.namespace.clone().unwrap();
-            let xmlns = Xmlns::new(Rc::new(url), prefix);
-            known_nss.insert_or_replace_if_not_found(xmlns.clone());
-            used_nss.insert(xmlns.clone());
-            Ok(Some(xmlns))
-        }
-        None => Ok(None),
-    }
-}
-
-/// Parses a `&str` into a `TinyStr16` used for XML namespace prefixes.
-fn parse_xmlns_prefix(prefix: &str) -> Result<TinyStr16, XmlCanonicalizeError> {
-    str_to_tinystr16(prefix)
-        .map_err(|_| XmlCanonicalizeError::InvalidXmlnsPrefix(prefix.to_owned()))
+    interner: &mut NsInterner,
+) -> Option<Xmlns> {
+    let prefix = n.prefix.as_deref()?;
+    let url = n.namespace.as_deref().unwrap_or_default();
+    let xmlns = match known_nss.find_by_prefix(prefix) {
+        Some(known) if *known.url == *url => known.clone(),
+        _ => Xmlns::new(interner.intern(url), XmlnsPrefix::new(prefix, interner)),
+    };
+    used_nss.insert(xmlns.clone());
+    Some(xmlns)
 }
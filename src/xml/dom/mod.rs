@@ -0,0 +1,318 @@
+pub mod query;
+#[allow(clippy::module_inception)]
+mod tests;
+
+use super::{
+    canonicalization::{
+        C14nSettings, CanonizedXml, XmlCanonicalizeError, XmlCanonizedDoc, digest::DigestAlgorithm,
+    },
+    xml_attr::XmlAttr,
+    xml_elem::{ElemId, XmlElem, XmlNode},
+};
+use query::{Query, QueryError};
+use ring::digest::Digest;
+use std::{borrow::Cow, collections::BTreeMap, fmt::Debug};
+use xml::namespace::{NS_XML_PREFIX, NS_XML_URI};
+
+/// Read-only view of an element of a parsed document.
+/// It borrows the document, so it is cheap to copy and can be canonicalized without parsing again.
+/// Names are compared by namespace URI and local name, prefixes are only informative.
+#[derive(Clone, Copy)]
+pub struct Element<'d> {
+    doc: &'d XmlCanonizedDoc,
+    id: ElemId,
+}
+
+impl<'d> Element<'d> {
+    pub(crate) fn new(doc: &'d XmlCanonizedDoc, id: ElemId) -> Self {
+        Self { doc, id }
+    }
+
+    pub(crate) fn id(&self) -> ElemId {
+        self.id
+    }
+
+    fn elem(&self) -> &'d XmlElem {
+        &self.doc.elems()[self.id]
+    }
+
+    /// Returns the local name, without the prefix
+    pub fn local_name(&self) -> &'d str {
+        &self.elem().local_name
+    }
+
+    /// Returns the prefix of the name, `None` for an unprefixed name
+    pub fn prefix(&self) -> Option<&'d str> {
+        self.elem().ns.as_ref().map(|ns| ns.prefix.as_str())
+    }
+
+    /// Returns the name as written in the document, e.g. `soapenv:Body`
+    pub fn qualified_name(&self) -> Cow<'d, str> {
+        self.elem().get_fullname()
+    }
+
+    /// Returns the namespace URI of the element, or an empty string if it is in no namespace
+    pub fn namespace_uri(&self) -> &'d str {
+        self.elem().get_ns_url()
+    }
+
+    /// Returns `true` if the element has the given namespace URI (`""` for none) and local name
+    pub fn is(&self, namespace_uri: &str, local_name: &str) -> bool {
+        self.local_name() == local_name && self.namespace_uri() == namespace_uri
+    }
+
+    /// Returns the parent element, `None` for the root element
+    pub fn parent(&self) -> Option<Element<'d>> {
+        self.elem().parent.map(|id| Element::new(self.doc, id))
+    }
+
+    /// Iterates over the ancestors, from the parent up to the root element
+    pub fn ancestors(&self) -> impl Iterator<Item = Element<'d>> + use<'d> {
+        std::iter::successors(self.parent(), |e| e.parent())
+    }
+
+    /// Iterates over all child nodes in document order
+    pub fn children(&self) -> impl Iterator<Item = Node<'d>> + use<'d> {
+        let doc = self.doc;
+        self.elem().children.iter().map(move |n| Node::new(doc, n))
+    }
+
+    /// Iterates over the child elements in document order
+    pub fn child_elements(&self) -> impl Iterator<Item = Element<'d>> + use<'d> {
+        self.children().filter_map(|n| match n {
+            Node::Element(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    /// Returns the first child element with the given namespace URI (`""` for none) and local name
+    pub fn first_child(&self, namespace_uri: &str, local_name: &str) -> Option<Element<'d>> {
+        self.child_elements()
+            .find(|e| e.is(namespace_uri, local_name))
+    }
+
+    /// Iterates over the descendant elements in document order, the element itself not included
+    pub fn descendants(&self) -> Descendants<'d> {
+        let mut stack: Vec<Element<'d>> = self.child_elements().collect();
+        stack.reverse();
+        Descendants { stack }
+    }
+
+    /// Iterates over the attributes, ordered by namespace URI and local name.
+    /// Namespace declarations are not attributes, see `namespaces_in_scope`.
+    pub fn attributes(&self) -> impl Iterator<Item = Attribute<'d>> + use<'d> {
+        self.elem().attrs.iter().map(|attr| Attribute { attr })
+    }
+
+    /// Returns the value of the attribute with the given namespace URI (`""` for an unprefixed attribute)
+    /// and local name
+    pub fn attribute(&self, namespace_uri: &str, local_name: &str) -> Option<&'d str> {
+        self.attributes()
+            .find(|a| a.local_name() == local_name && a.namespace_uri() == namespace_uri)
+            .map(|a| a.value())
+    }
+
+    /// Returns the text content: the texts of the element and all its descendants concatenated,
+    /// without comments and processing instructions. CDATA sections are included as text.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.push_text(&mut text);
+        text
+    }
+
+    fn push_text(&self, text: &mut String) {
+        for child in self.children() {
+            match child {
+                Node::Text(t) => text.push_str(t),
+                Node::Element(e) => e.push_text(text),
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the namespace URI bound to the prefix at this element (`""` for the default namespace).
+    /// The `xml` prefix is always bound, an undeclared default namespace (`xmlns=""`) is not.
+    pub fn lookup_namespace_uri(&self, prefix: &str) -> Option<&'d str> {
+        if prefix == NS_XML_PREFIX {
+            return Some(NS_XML_URI);
+        }
+        std::iter::once(*self)
+            .chain(self.ancestors())
+            .find_map(|e| {
+                e.elem()
+                    .declared_nss
+                    .iter()
+                    .find(|ns| *ns.prefix == *prefix)
+            })
+            .map(|ns| &*ns.url)
+            .filter(|url| !url.is_empty())
+    }
+
+    /// Returns the namespaces in scope at this element by prefix (`""` for the default namespace),
+    /// without the implicit `xml` and `xmlns` prefixes
+    pub fn namespaces_in_scope(&self) -> BTreeMap<&'d str, &'d str> {
+        let mut in_scope = BTreeMap::new();
+        // Od elementu ke koreni, blizsi deklarace vyhrava
+        for e in std::iter::once(*self).chain(self.ancestors()) {
+            for ns in e.elem().declared_nss.iter().filter(|ns| !ns.is_reserved()) {
+                in_scope.entry(ns.prefix.as_str()).or_insert(&*ns.url);
+            }
+        }
+        in_scope.retain(|_, url| !url.is_empty());
+        in_scope
+    }
+
+    /// Selects elements by a query relative to this element, see `Query`.
+    /// `namespaces` bind the prefixes used in the query to namespace URIs.
+    pub fn select(
+        &self,
+        expr: &str,
+        namespaces: &[(&str, &str)],
+    ) -> Result<Vec<Element<'d>>, QueryError> {
+        Query::parse(expr, namespaces)?.select_from(*self)
+    }
+
+    /// Converts the subtree of this element to its canonical form, as the apex of the output.
+    /// Unlike `XmlCanonizedDoc::canonize_by_id`, the `#WithComments` algorithms keep comments.
+    pub fn canonize(&self, settings: &C14nSettings) -> CanonizedXml {
+        self.doc.canonize_subtree(self.id, settings)
+    }
+
+    /// Computes the digest of the canonical form of the subtree of this element,
+    /// without holding the canonical XML in memory. See `canonize`.
+    pub fn digest(&self, settings: &C14nSettings, algorithm: DigestAlgorithm) -> Digest {
+        self.doc.digest_subtree(self.id, settings, algorithm)
+    }
+
+    /// Dereferences the `URI` of this `ds:Reference`, applies the enveloped-signature transform
+    /// and converts the result to its canonical form. Only the `ds:Signature` holding this reference
+    /// is left out, so a document may carry more signatures. Comments are left out as by
+    /// `XmlCanonizedDoc::canonize_enveloped`. Fails if this element is not inside a `ds:Signature`
+    /// or the signature is not in the referenced node set.
+    pub fn canonize_enveloped(
+        &self,
+        settings: &C14nSettings,
+    ) -> Result<CanonizedXml, XmlCanonicalizeError> {
+        self.doc.canonize_enveloped_reference(self.id, settings)
+    }
+
+    /// Computes the digest of this `ds:Reference` after the enveloped-signature transform,
+    /// without holding the canonical XML in memory. See `canonize_enveloped`.
+    pub fn digest_enveloped(
+        &self,
+        settings: &C14nSettings,
+        algorithm: DigestAlgorithm,
+    ) -> Result<Digest, XmlCanonicalizeError> {
+        self.doc
+            .digest_enveloped_reference(self.id, settings, algorithm)
+    }
+}
+
+impl PartialEq for Element<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.doc, other.doc) && self.id == other.id
+    }
+}
+
+impl Eq for Element<'_> {}
+
+impl Debug for Element<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Element")
+            .field("name", &self.qualified_name())
+            .field("namespace_uri", &self.namespace_uri())
+            .finish()
+    }
+}
+
+/// Descendant elements in document order, see `Element::descendants`
+pub struct Descendants<'d> {
+    /// Elements still to be visited, the next one on top
+    stack: Vec<Element<'d>>,
+}
+
+impl<'d> Iterator for Descendants<'d> {
+    type Item = Element<'d>;
+
+    fn next(&mut self) -> Option<Element<'d>> {
+        let next = self.stack.pop()?;
+        let first_child = self.stack.len();
+        self.stack.extend(next.child_elements());
+        self.stack[first_child..].reverse();
+        Some(next)
+    }
+}
+
+/// Read-only view of an attribute of an element
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Attribute<'d> {
+    attr: &'d XmlAttr,
+}
+
+impl<'d> Attribute<'d> {
+    /// Returns the local name, without the prefix
+    pub fn local_name(&self) -> &'d str {
+        &self.attr.local_name
+    }
+
+    /// Returns the prefix of the name, `None` for an unprefixed attribute
+    pub fn prefix(&self) -> Option<&'d str> {
+        self.attr.ns.as_ref().map(|ns| ns.prefix.as_str())
+    }
+
+    /// Returns the name as written in the document, e.g. `wsu:Id`
+    pub fn qualified_name(&self) -> Cow<'d, str> {
+        self.attr.get_fullname()
+    }
+
+    /// Returns the namespace URI, or an empty string for an unprefixed attribute
+    pub fn namespace_uri(&self) -> &'d str {
+        self.attr.get_ns_url()
+    }
+
+    /// Returns the normalized value of the attribute
+    pub fn value(&self) -> &'d str {
+        &self.attr.value
+    }
+}
+
+impl Debug for Attribute<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Attribute")
+            .field("name", &self.qualified_name())
+            .field("namespace_uri", &self.namespace_uri())
+            .field("value", &self.value())
+            .finish()
+    }
+}
+
+/// A child node of an element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Node<'d> {
+    Element(Element<'d>),
+
+    /// Text content, adjacent texts and CDATA sections are merged
+    Text(&'d str),
+
+    /// Text of the comment, without `<!--` and `-->`
+    Comment(&'d str),
+
+    /// Processing instruction `<?target data?>`, `data` is empty if the PI has none
+    ProcessingInstruction {
+        target: &'d str,
+        data: &'d str,
+    },
+}
+
+impl<'d> Node<'d> {
+    fn new(doc: &'d XmlCanonizedDoc, node: &'d XmlNode) -> Self {
+        match node {
+            XmlNode::Elem(id) => Node::Element(Element::new(doc, *id)),
+            XmlNode::Text(text) => Node::Text(text),
+            XmlNode::Comment(text) => Node::Comment(text),
+            XmlNode::ProcessingInstruction { target, data } => {
+                Node::ProcessingInstruction { target, data }
+            }
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use super::Element;
+use crate::tools::{is_ncname, is_ncname_char};
+use crate::xml::{
+    canonicalization::{XmlCanonicalizeError, XmlCanonizedDoc},
+    xml_elem::ElemId,
+};
+use std::collections::BTreeSet;
+use xml::namespace::{NS_XML_PREFIX, NS_XML_URI};
+
+/// A compiled query selecting elements of a parsed document, a small subset of XPath 1.0:
+/// - `/` and `//` steps (`child` and `descendant-or-self::node()/child` axes),
+///   an expression without the leading `/` is relative to the context element,
+/// - names `prefix:local` with the prefix bound by the caller (`xml` is always bound), `{namespace-uri}local`,
+///   unprefixed names in no namespace, `*`, `prefix:*` and `{namespace-uri}*`,
+/// - predicates `[@name]`, `[@name='value']` and `[n]` (position among the matches of one context element),
+/// - `id('value')` as the first step, for `wsu:Id`, `Id` and `xml:id` attributes.
+///
+/// E.g. `/s:Envelope/s:Header//wsse:Security[@s:mustUnderstand='1']` or `id('body')/*[1]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    start: Start,
+    steps: Vec<Step>,
+}
+
+/// Error of parsing or evaluating a query
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    #[error("Invalid query at position {position}: {message}")]
+    Syntax {
+        position: usize,
+        message: &'static str,
+    },
+
+    #[error("Namespace prefix '{0}' used in the query is not bound")]
+    UnboundPrefix(String),
+
+    #[error("ID '{0}' is not unique in the XML document")]
+    DuplicateId(String),
+}
+
+/// Where the evaluation starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Start {
+    /// The context element
+    Context,
+
+    /// The document, the parent of the root element
+    Document,
+
+    /// The elements with the IDs
+    Ids(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    /// `//` before the step
+    descendants: bool,
+    name: NameTest,
+    predicates: Vec<Predicate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NameTest {
+    Any,
+    AnyInNamespace(String),
+    Name { ns_url: String, local_name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Predicate {
+    HasAttr {
+        ns_url: String,
+        local_name: String,
+    },
+    AttrEquals {
+        ns_url: String,
+        local_name: String,
+        value: String,
+    },
+    Position(usize),
+}
+
+/// Node of the evaluation: the document or an element
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Context {
+    Document,
+    Elem(ElemId),
+}
+
+impl Query {
+    /// Parses the query. `namespaces` bind the prefixes used in it to namespace URIs,
+    /// they are independent of the prefixes in the queried documents.
+    pub fn parse(expr: &str, namespaces: &[(&str, &str)]) -> Result<Self, QueryError> {
+        QueryParser {
+            expr,
+            pos: 0,
+            namespaces,
+        }
+        .parse()
+    }
+
+    /// Selects the elements of the document, in document order.
+    /// A relative query is evaluated from the document, so its first step matches the root element.
+    /// Fails if an ID used by `id()` is not unique.
+    pub fn select<'d>(&self, doc: &'d XmlCanonizedDoc) -> Result<Vec<Element<'d>>, QueryError> {
+        self.evaluate(doc, Context::Document)
+    }
+
+    /// Selects the elements relative to the context element, in document order.
+    /// Fails if an ID used by `id()` is not unique.
+    pub fn select_from<'d>(&self, context: Element<'d>) -> Result<Vec<Element<'d>>, QueryError> {
+        self.evaluate(context.doc, Context::Elem(context.id))
+    }
+
+    fn evaluate<'d>(
+        &self,
+        doc: &'d XmlCanonizedDoc,
+        context: Context,
+    ) -> Result<Vec<Element<'d>>, QueryError> {
+        let mut current: BTreeSet<Context> = match &self.start {
+            Start::Context => BTreeSet::from([context]),
+            Start::Document => BTreeSet::from([Context::Document]),
+            Start::Ids(ids) => ids
+                .iter()
+                .filter_map(|id| match doc.element_by_id(id) {
+                    Ok(elem) => Some(Ok(Context::Elem(elem.id))),
+                    Err(e) => match e.kind() {
+                        XmlCanonicalizeError::DuplicateId(id) => {
+                            Some(Err(QueryError::DuplicateId(id.clone())))
+                        }
+                        _ => None,
+                    },
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        for step in self.steps.iter() {
+            let mut next = BTreeSet::new();
+            for node in step.contexts(doc, &current) {
+                next.extend(step.select_children(doc, node).map(|e| Context::Elem(e.id)));
+            }
+            current = next;
+        }
+
+        Ok(current
+            .into_iter()
+            .filter_map(|node| match node {
+                Context::Elem(id) => Some(Element::new(doc, id)),
+                Context::Document => None,
+            })
+            .collect())
+    }
+}
+
+impl Step {
+    /// Returns the nodes whose children are tested: the current nodes, or with `//` also all their descendants
+    fn contexts(&self, doc: &XmlCanonizedDoc, current: &BTreeSet<Context>) -> BTreeSet<Context> {
+        if !self.descendants {
+            return current.clone();
+        }
+        let mut contexts = current.clone();
+        for node in current.iter() {
+            let elem = match node {
+                Context::Document => {
+                    contexts.insert(Context::Elem(doc.root_element().id));
+                    doc.root_element()
+                }
+                Context::Elem(id) => Element::new(doc, *id),
+            };
+            contexts.extend(elem.descendants().map(|e| Context::Elem(e.id)));
+        }
+        contexts
+    }
+
+    /// Returns the child elements of the node which pass the name test and the predicates
+    fn select_children<'d>(
+        &self,
+        doc: &'d XmlCanonizedDoc,
+        node: Context,
+    ) -> impl Iterator<Item = Element<'d>> + use<'d, '_> {
+        let children: Vec<Element<'d>> = match node {
+            Context::Document => vec![doc.root_element()],
+            Context::Elem(id) => Element::new(doc, id).child_elements().collect(),
+        };
+        let mut selected: Vec<Element<'d>> = children
+            .into_iter()
+            .filter(|e| self.name.matches(e))
+            .collect();
+        for predicate in self.predicates.iter() {
+            selected = match predicate {
+                Predicate::Position(position) => {
+                    selected.into_iter().skip(position - 1).take(1).collect()
+                }
+                _ => selected
+                    .into_iter()
+                    .filter(|e| predicate.matches(e))
+                    .collect(),
+            };
+        }
+        selected.into_iter()
+    }
+}
+
+impl NameTest {
+    fn matches(&self, elem: &Element) -> bool {
+        match self {
+            NameTest::Any => true,
+            NameTest::AnyInNamespace(ns_url) => elem.namespace_uri() == ns_url,
+            NameTest::Name { ns_url, local_name } => elem.is(ns_url, local_name),
+        }
+    }
+}
+
+impl Predicate {
+    fn matches(&self, elem: &Element) -> bool {
+        match self {
+            Predicate::HasAttr { ns_url, local_name } => {
+                elem.attribute(ns_url, local_name).is_some()
+            }
+            Predicate::AttrEquals {
+                ns_url,
+                local_name,
+                value,
+            } => elem.attribute(ns_url, local_name) == Some(value.as_str()),
+            Predicate::Position(_) => true,
+        }
+    }
+}
+
+/// Recursive descent parser of queries
+struct QueryParser<'q> {
+    expr: &'q str,
+
+    /// Position of the next character, in bytes
+    pos: usize,
+    namespaces: &'q [(&'q str, &'q str)],
+}
+
+impl<'q> QueryParser<'q> {
+    fn parse(mut self) -> Result<Query, QueryError> {
+        self.skip_whitespace();
+        let start = if self.eat("id(") {
+            let ids = self.literal()?;
+            self.expect(")")?;
+            Start::Ids(ids.split_whitespace().map(str::to_owned).collect())
+        } else if self.rest().starts_with('/') {
+            Start::Document
+        } else {
+            Start::Context
+        };
+
+        let mut steps = Vec::new();
+        // Relativni cesta zacina krokem bez lomitka
+        if start == Start::Context {
+            steps.push(self.step(false)?);
+        }
+        loop {
+            self.skip_whitespace();
+            if self.rest().is_empty() {
+                break;
+            }
+            let descendants = if self.eat("//") {
+                true
+            } else if self.eat("/") {
+                false
+            } else {
+                return Err(self.error("expected '/' or '//'"));
+            };
+            steps.push(self.step(descendants)?);
+        }
+        Ok(Query { start, steps })
+    }
+
+    fn step(&mut self, descendants: bool) -> Result<Step, QueryError> {
+        self.skip_whitespace();
+        let name = if self.eat("*") {
+            NameTest::Any
+        } else {
+            let (ns_url, local_name) = self.qualified_name(true)?;
+            match local_name.as_str() {
+                "*" => NameTest::AnyInNamespace(ns_url),
+                _ => NameTest::Name { ns_url, local_name },
+            }
+        };
+
+        let mut predicates = Vec::new();
+        while self.eat("[") {
+            self.skip_whitespace();
+            let predicate = if self.eat("@") {
+                let (ns_url, local_name) = self.qualified_name(false)?;
+                self.skip_whitespace();
+                if self.eat("=") {
+                    let value = self.literal()?;
+                    Predicate::AttrEquals {
+                        ns_url,
+                        local_name,
+                        value,
+                    }
+                } else {
+                    Predicate::HasAttr { ns_url, local_name }
+                }
+            } else {
+                let position = self.number()?;
+                Predicate::Position(position)
+            };
+            self.skip_whitespace();
+            self.expect("]")?;
+            predicates.push(predicate);
+        }
+        Ok(Step {
+            descendants,
+            name,
+            predicates,
+        })
+    }
+
+    /// Parses `prefix:local`, `{uri}local` or `local` (in no namespace) and returns the namespace URI
+    /// and the local name. With `allow_wildcard` the local name of a prefixed name may be `*`.
+    fn qualified_name(&mut self, allow_wildcard: bool) -> Result<(String, String), QueryError> {
+        if self.eat("{") {
+            let end = self
+                .rest()
+                .find('}')
+                .ok_or_else(|| self.error("missing '}'"))?;
+            let ns_url = self.rest()[..end].to_owned();
+            self.pos += end + 1;
+            if allow_wildcard && self.eat("*") {
+                return Ok((ns_url, "*".to_owned()));
+            }
+            return Ok((ns_url, self.ncname()?.to_owned()));
+        }
+
+        let first = self.ncname()?;
+        if !self.eat(":") {
+            return Ok((String::new(), first.to_owned()));
+        }
+        let ns_url = self
+            .namespaces
+            .iter()
+            .copied()
+            .chain(std::iter::once((NS_XML_PREFIX, NS_XML_URI)))
+            .find(|(prefix, _)| *prefix == first)
+            .map(|(_, url)| url.to_owned())
+            .ok_or_else(|| QueryError::UnboundPrefix(first.to_owned()))?;
+        if allow_wildcard && self.eat("*") {
+            return Ok((ns_url, "*".to_owned()));
+        }
+        Ok((ns_url, self.ncname()?.to_owned()))
+    }
+
+    fn ncname(&mut self) -> Result<&'q str, QueryError> {
+        let rest: &'q str = &self.expr[self.pos..];
+        let len = rest.find(|c| !is_ncname_char(c)).unwrap_or(rest.len());
+        let name = &rest[..len];
+        if !is_ncname(name) {
+            return Err(self.error("expected a name"));
+        }
+        self.pos += len;
+        Ok(name)
+    }
+
+    /// Parses a string literal in single or double quotes
+    fn literal(&mut self) -> Result<String, QueryError> {
+        self.skip_whitespace();
+        let quote = match self.rest().chars().next() {
+            Some(q @ ('\'' | '"')) => q,
+            _ => return Err(self.error("expected a quoted string")),
+        };
+        let rest = &self.rest()[1..];
+        let end = rest
+            .find(quote)
+            .ok_or_else(|| self.error("unterminated string"))?;
+        let value = rest[..end].to_owned();
+        self.pos += end + 2;
+        Ok(value)
+    }
+
+    /// Parses a position, counted from 1
+    fn number(&mut self) -> Result<usize, QueryError> {
+        let len = self
+            .rest()
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest().len());
+        match self.rest()[..len].parse::<usize>() {
+            Ok(n) if n > 0 => {
+                self.pos += len;
+                Ok(n)
+            }
+            _ => Err(self.error("expected '@' or a position from 1")),
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.expr[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes the token if it comes next
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<(), QueryError> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(match token {
+                ")" => "expected ')'",
+                "]" => "expected ']'",
+                _ => "unexpected character",
+            })),
+        }
+    }
+
+    fn error(&self, message: &'static str) -> QueryError {
+        QueryError::Syntax {
+            position: self.pos,
+            message,
+        }
+    }
+}
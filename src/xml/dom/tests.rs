@@ -0,0 +1,246 @@
+#[cfg(test)]
+mod tests {
+    use crate::xml::{
+        canonicalization::{
+            C14nAlgorithm, C14nSettings, WhitespaceMode, XmlCanonicalizeError, XmlCanonizedDoc,
+        },
+        dom::{
+            Element, Node,
+            query::{Query, QueryError},
+        },
+    };
+
+    const SOAP_NS: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+    const WSU_NS: &str =
+        "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd";
+
+    const DOC: &str = r#"<soapenv:Envelope xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd">
+    <soapenv:Header/>
+    <soapenv:Body wsu:Id="body">
+        <m:Order xmlns:m="http://m.m" xmlns="http://d.d" id="7" m:state="new">
+            <Item>A<!--c-->B<![CDATA[<C>]]></Item>
+            <m:Item xml:lang="cs"><?pi data?>D</m:Item>
+            <Note xmlns="">plain</Note>
+        </m:Order>
+    </soapenv:Body>
+</soapenv:Envelope>"#;
+
+    fn parse() -> XmlCanonizedDoc {
+        let settings = C14nSettings::default().with_whitespace(WhitespaceMode::Normalize);
+        XmlCanonizedDoc::parse(DOC, &settings).unwrap()
+    }
+
+    /// Returns the local names of the elements
+    fn names<'d>(elems: impl Iterator<Item = Element<'d>>) -> Vec<&'d str> {
+        elems.map(|e| e.local_name()).collect()
+    }
+
+    #[test]
+    fn test_navigation() {
+        let doc = parse();
+        let root = doc.root_element();
+        assert_eq!(root.qualified_name(), "soapenv:Envelope");
+        assert_eq!(root.prefix(), Some("soapenv"));
+        assert!(root.is(SOAP_NS, "Envelope"));
+        assert_eq!(root.parent(), None);
+        assert_eq!(names(root.child_elements()), ["Header", "Body"]);
+
+        let body = root.first_child(SOAP_NS, "Body").unwrap();
+        assert_eq!(body, doc.element_by_id("body").unwrap());
+        assert_eq!(body.parent(), Some(root));
+        assert_eq!(names(body.descendants()), ["Order", "Item", "Item", "Note"]);
+        assert_eq!(names(root.descendants()).len(), 6);
+        assert_eq!(
+            doc.element_by_id("missing").unwrap_err(),
+            XmlCanonicalizeError::IdNotFound("missing".to_owned())
+        );
+
+        let order = body.first_child("http://m.m", "Order").unwrap();
+        let items: Vec<_> = order.child_elements().collect();
+        assert_eq!(items[0].namespace_uri(), "http://d.d");
+        assert_eq!(items[0].prefix(), None);
+        assert_eq!(items[1].namespace_uri(), "http://m.m");
+        assert_eq!(items[2].namespace_uri(), "");
+        assert_eq!(order.first_child("http://d.d", "Note"), None);
+        assert_eq!(order.first_child("", "Note"), Some(items[2]));
+        assert_eq!(names(items[2].ancestors()), ["Order", "Body", "Envelope"]);
+    }
+
+    #[test]
+    fn test_attributes_and_text() {
+        let doc = parse();
+        let body = doc.element_by_id("body").unwrap();
+        assert_eq!(body.attribute(WSU_NS, "Id"), Some("body"));
+        assert_eq!(body.attribute("", "Id"), None);
+
+        let order = body.child_elements().next().unwrap();
+        // Atributy bez prefixu nejsou v default namespace
+        assert_eq!(order.attribute("", "id"), Some("7"));
+        assert_eq!(order.attribute("http://d.d", "id"), None);
+        assert_eq!(order.attribute("http://m.m", "state"), Some("new"));
+        let names: Vec<_> = order
+            .attributes()
+            .map(|a| (a.namespace_uri(), a.qualified_name()))
+            .collect();
+        assert_eq!(names, [("", "id".into()), ("http://m.m", "m:state".into())]);
+
+        let items: Vec<_> = order.child_elements().collect();
+        assert_eq!(items[0].text(), "AB<C>");
+        assert_eq!(order.text(), "AB<C>Dplain");
+        assert_eq!(
+            items[0].children().collect::<Vec<_>>(),
+            [Node::Text("A"), Node::Comment("c"), Node::Text("B<C>")]
+        );
+        assert_eq!(
+            items[1].children().next(),
+            Some(Node::ProcessingInstruction {
+                target: "pi",
+                data: "data"
+            })
+        );
+        assert_eq!(
+            items[1].attribute("http://www.w3.org/XML/1998/namespace", "lang"),
+            Some("cs")
+        );
+    }
+
+    #[test]
+    fn test_namespace_resolution() {
+        let doc = parse();
+        let order = doc
+            .element_by_id("body")
+            .unwrap()
+            .child_elements()
+            .next()
+            .unwrap();
+        let note = order.first_child("", "Note").unwrap();
+
+        assert_eq!(order.lookup_namespace_uri("m"), Some("http://m.m"));
+        assert_eq!(order.lookup_namespace_uri(""), Some("http://d.d"));
+        assert_eq!(order.lookup_namespace_uri("soapenv"), Some(SOAP_NS));
+        assert_eq!(
+            order.lookup_namespace_uri("xml"),
+            Some("http://www.w3.org/XML/1998/namespace")
+        );
+        assert_eq!(order.lookup_namespace_uri("x"), None);
+        assert_eq!(note.lookup_namespace_uri(""), None);
+        assert_eq!(doc.root_element().lookup_namespace_uri("m"), None);
+
+        assert_eq!(
+            order.namespaces_in_scope().into_iter().collect::<Vec<_>>(),
+            [
+                ("", "http://d.d"),
+                ("m", "http://m.m"),
+                ("soapenv", SOAP_NS),
+                ("wsu", WSU_NS)
+            ]
+        );
+        assert!(!note.namespaces_in_scope().contains_key(""));
+    }
+
+    #[test]
+    fn test_canonize_element() {
+        let doc = parse();
+        let body = doc.element_by_id("body").unwrap();
+        let settings = C14nSettings::default();
+        assert_eq!(
+            body.canonize(&settings),
+            doc.canonize_by_id("body", &settings).unwrap()
+        );
+
+        let note = body.descendants().last().unwrap();
+        assert_eq!(note.canonize(&settings).as_str(), "<Note>plain</Note>");
+        assert_eq!(
+            note.canonize(&C14nSettings::new(C14nAlgorithm::Inclusive))
+                .as_str(),
+            r#"<Note xmlns:m="http://m.m" xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd">plain</Note>"#
+        );
+    }
+
+    #[test]
+    fn test_query_paths() {
+        let doc = parse();
+        let ns = [("s", SOAP_NS), ("m", "http://m.m"), ("d", "http://d.d")];
+        let select = |expr: &str| names(doc.select(expr, &ns).unwrap().into_iter());
+
+        assert_eq!(select("/s:Envelope/s:Body/m:Order/d:Item"), ["Item"]);
+        assert_eq!(select("s:Envelope/*"), ["Header", "Body"]);
+        assert_eq!(select("//m:Order/*"), ["Item", "Item", "Note"]);
+        assert_eq!(select("/s:Envelope//m:*"), ["Order", "Item"]);
+        assert_eq!(select("//{http://d.d}Item"), ["Item"]);
+        assert_eq!(select("//{http://d.d}*"), ["Item"]);
+        // Nazev bez prefixu je bez namespace, i kdyz ma dokument default namespace
+        assert_eq!(select("//Note"), ["Note"]);
+        assert_eq!(select("//Item"), Vec::<&str>::new());
+        assert_eq!(select("/s:Body"), Vec::<&str>::new());
+
+        let body = doc.element_by_id("body").unwrap();
+        assert_eq!(
+            names(body.select("m:Order/m:Item", &ns).unwrap().into_iter()),
+            ["Item"]
+        );
+        assert_eq!(
+            body.select("/s:Envelope", &ns).unwrap(),
+            [doc.root_element()]
+        );
+        assert_eq!(
+            Query::parse("//x:Item", &ns).unwrap_err(),
+            QueryError::UnboundPrefix("x".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_query_predicates_and_ids() {
+        let doc = parse();
+        let ns = [("m", "http://m.m"), ("wsu", WSU_NS)];
+        let select = |expr: &str| doc.select(expr, &ns).unwrap();
+
+        let order = select("//m:Order");
+        assert_eq!(select("//*[@id]"), order);
+        assert_eq!(select("//*[@id='7'][@m:state=\"new\"]"), order);
+        assert!(select("//*[@id='8']").is_empty());
+        assert_eq!(select("//*[@wsu:Id='body']"), select("id('body')"));
+        assert_eq!(select("id('body')/*"), order);
+        assert!(select("id('missing')/*").is_empty());
+
+        let items: Vec<_> = order[0].child_elements().collect();
+        assert_eq!(select("//m:Order/*[1]"), [items[0]]);
+        assert_eq!(select("//m:Order/*[3]"), [items[2]]);
+        assert!(select("//m:Order/*[4]").is_empty());
+        // Pozice se pocita az po predchozich predikatech
+        assert_eq!(select("//m:Order/*[@xml:lang][1]"), [items[1]]);
+        assert!(select("//m:Order/*[1][@xml:lang]").is_empty());
+
+        // Jmena v dotazu plati podle stejnych pravidel jako jmena v dokumentu
+        let dotted =
+            XmlCanonizedDoc::parse("<a><b\u{B7}c/></a>", &C14nSettings::default()).unwrap();
+        assert_eq!(dotted.select("/a/b\u{B7}c", &[]).unwrap().len(), 1);
+
+        let dup =
+            XmlCanonizedDoc::parse(r#"<a><b Id="x"/><c Id="x"/></a>"#, &C14nSettings::default())
+                .unwrap();
+        assert_eq!(
+            dup.select("id('x')", &[]).unwrap_err(),
+            QueryError::DuplicateId("x".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_query_syntax_errors() {
+        let error = |expr: &str| match Query::parse(expr, &[]).unwrap_err() {
+            QueryError::Syntax { position, message } => (position, message),
+            e => panic!("unexpected error {e}"),
+        };
+        assert_eq!(error(""), (0, "expected a name"));
+        assert_eq!(error("/a/"), (3, "expected a name"));
+        assert_eq!(error("/1a"), (1, "expected a name"));
+        assert_eq!(error("/a/-b"), (3, "expected a name"));
+        assert_eq!(error("/a b"), (3, "expected '/' or '//'"));
+        assert_eq!(error("/a[0]"), (3, "expected '@' or a position from 1"));
+        assert_eq!(error("/a[@b='c']x"), (10, "expected '/' or '//'"));
+        assert_eq!(error("/a[@b='c'"), (9, "expected ']'"));
+        assert_eq!(error("id('c'"), (6, "expected ')'"));
+        assert_eq!(error("id(c)"), (3, "expected a quoted string"));
+        assert_eq!(error("/{urn:x"), (2, "missing '}'"));
+    }
+}
@@ -0,0 +1,38 @@
+//! Character escaping of the canonical XML output, as defined by Canonical XML 1.0, section 2.3.
+
+/// Escapes a text node: `&`, `<`, `>` and `#xD` are replaced by character references.
+pub(crate) fn escape_text(text: &str) -> String {
+    let mut escaped_text = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped_text.push_str("&amp;"),
+            '<' => escaped_text.push_str("&lt;"),
+            '>' => escaped_text.push_str("&gt;"),
+            '\r' => escaped_text.push_str("&#xD;"),
+            _ => escaped_text.push(ch),
+        }
+    }
+
+    escaped_text
+}
+
+/// Escapes an attribute value (or a namespace URI):
+/// `&`, `<`, `"`, `#x9`, `#xA` and `#xD` are replaced by character references.
+pub(crate) fn escape_attr_value(value: &str) -> String {
+    let mut escaped_value = String::with_capacity(value.len());
+
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped_value.push_str("&amp;"),
+            '<' => escaped_value.push_str("&lt;"),
+            '"' => escaped_value.push_str("&quot;"),
+            '\t' => escaped_value.push_str("&#x9;"),
+            '\n' => escaped_value.push_str("&#xA;"),
+            '\r' => escaped_value.push_str("&#xD;"),
+            _ => escaped_value.push(ch),
+        }
+    }
+
+    escaped_value
+}
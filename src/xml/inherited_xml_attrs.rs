@@ -0,0 +1,213 @@
+use super::xml_attr::XmlAttr;
+use std::{borrow::Cow, collections::BTreeMap};
+
+/// Attributes in the `xml` namespace (`xml:lang`, `xml:space`, `xml:base`, `xml:id`, ...)
+/// which an element inherits from its ancestors.
+/// Inclusive canonicalization writes them on the apex element of a document subset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct InheritedXmlAttrs {
+    /// The nearest value of every `xml:*` attribute, keyed by local name
+    nearest: BTreeMap<String, String>,
+
+    /// All `xml:base` values of the ancestors, outermost first
+    bases: Vec<String>,
+}
+
+impl InheritedXmlAttrs {
+    /// Returns the attributes a child of an element inherits:
+    /// these inherited ones, overridden by the element's own `xml:*` attributes.
+    pub fn for_children<'a>(&self, elem_attrs: impl Iterator<Item = &'a XmlAttr>) -> Self {
+        let mut inherited = self.clone();
+        for a in elem_attrs.filter(|a| a.is_xml_attr()) {
+            if a.local_name == "base" {
+                inherited.bases.push(a.value.clone());
+            }
+            inherited
+                .nearest
+                .insert(a.local_name.clone(), a.value.clone());
+        }
+        inherited
+    }
+
+    /// Iterates over the nearest value of every inherited attribute as (local name, value)
+    pub fn iter_nearest(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.nearest.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Joins all inherited `xml:base` values, optionally followed by the element's own one.
+    /// Returns `None` if no ancestor declares `xml:base`.
+    /// This is the "xml:base fixup" of Canonical XML 1.1.
+    pub fn joined_base(&self, own_base: Option<&str>) -> Option<String> {
+        if self.bases.is_empty() {
+            return None;
+        }
+        let joined = self
+            .bases
+            .iter()
+            .map(String::as_str)
+            .chain(own_base)
+            .fold(String::new(), |base, reference| {
+                join_uri_references(&base, reference)
+            });
+        Some(joined)
+    }
+}
+
+/// Joins a relative URI reference to a base URI according to RFC 3986, section 5.2.2,
+/// as modified by Canonical XML 1.1: the base does not need to be absolute,
+/// and leading `..` segments of a relative path that cannot be resolved are kept.
+pub(crate) fn join_uri_references(base: &str, reference: &str) -> String {
+    if base.is_empty() {
+        return reference.to_owned();
+    }
+
+    let b = UriRef::parse(base);
+    let r = UriRef::parse(reference);
+
+    let t = if r.scheme.is_some() {
+        UriRef {
+            path: Cow::Owned(remove_dot_segments(&r.path)),
+            ..r
+        }
+    } else if r.authority.is_some() {
+        UriRef {
+            scheme: b.scheme,
+            path: Cow::Owned(remove_dot_segments(&r.path)),
+            ..r
+        }
+    } else if r.path.is_empty() {
+        UriRef {
+            scheme: b.scheme,
+            authority: b.authority,
+            path: b.path,
+            query: r.query.or(b.query),
+            fragment: r.fragment,
+        }
+    } else {
+        let path = if r.path.starts_with('/') {
+            r.path.into_owned()
+        } else {
+            merge_paths(&b, &r.path)
+        };
+        UriRef {
+            scheme: b.scheme,
+            authority: b.authority,
+            path: Cow::Owned(remove_dot_segments(&path)),
+            query: r.query,
+            fragment: r.fragment,
+        }
+    };
+    t.to_string()
+}
+
+/// Components of a URI reference (RFC 3986, appendix B)
+struct UriRef<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: Cow<'a, str>,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> UriRef<'a> {
+    fn parse(s: &'a str) -> Self {
+        let (s, fragment) = match s.split_once('#') {
+            Some((s, f)) => (s, Some(f)),
+            None => (s, None),
+        };
+        let (s, query) = match s.split_once('?') {
+            Some((s, q)) => (s, Some(q)),
+            None => (s, None),
+        };
+        let (scheme, s) = match s.split_once(':') {
+            Some((scheme, rest)) if !scheme.is_empty() && !scheme.contains('/') => {
+                (Some(scheme), rest)
+            }
+            _ => (None, s),
+        };
+        let (authority, path) = match s.strip_prefix("//") {
+            Some(rest) => {
+                let end = rest.find('/').unwrap_or(rest.len());
+                (Some(&rest[..end]), &rest[end..])
+            }
+            None => (None, s),
+        };
+        Self {
+            scheme,
+            authority,
+            path: Cow::Borrowed(path),
+            query,
+            fragment,
+        }
+    }
+}
+
+impl std::fmt::Display for UriRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(scheme) = self.scheme {
+            write!(f, "{scheme}:")?;
+        }
+        if let Some(authority) = self.authority {
+            write!(f, "//{authority}")?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(query) = self.query {
+            write!(f, "?{query}")?;
+        }
+        if let Some(fragment) = self.fragment {
+            write!(f, "#{fragment}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Merges a relative path with the path of the base URI (RFC 3986, section 5.2.3)
+fn merge_paths(base: &UriRef, reference_path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        format!("/{reference_path}")
+    } else {
+        match base.path.rfind('/') {
+            Some(i) => format!("{}{reference_path}", &base.path[..=i]),
+            None => reference_path.to_owned(),
+        }
+    }
+}
+
+/// Removes `.` and `..` segments from a path (RFC 3986, section 5.2.4).
+/// A `..` segment that would climb above the start of a relative path is kept.
+fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let segments: Vec<&str> = path.split('/').skip(usize::from(absolute)).collect();
+    let mut output: Vec<&str> = Vec::with_capacity(segments.len());
+
+    for (i, segment) in segments.iter().enumerate() {
+        let is_last = i + 1 == segments.len();
+        match *segment {
+            "." => {
+                if is_last {
+                    output.push("");
+                }
+            }
+            ".." => {
+                match output.last() {
+                    Some(&last) if last != ".." => {
+                        output.pop();
+                    }
+                    _ if !absolute => output.push(".."),
+                    _ => {}
+                }
+                if is_last {
+                    output.push("");
+                }
+            }
+            s => output.push(s),
+        }
+    }
+
+    let joined = output.join("/");
+    if absolute {
+        format!("/{joined}")
+    } else {
+        joined
+    }
+}
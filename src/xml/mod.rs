@@ -1,5 +1,9 @@
+pub mod builder;
 pub mod canonicalization;
+pub mod dom;
+pub(crate) mod escape;
+pub(crate) mod inherited_xml_attrs;
 pub(crate) mod xml_attr;
 pub(crate) mod xml_elem;
 pub(crate) mod xmlns;
-pub(crate) mod xmlns_collection;
+pub(crate) mod xmlns_scope;
@@ -1,11 +1,15 @@
-use crate::tools::StrCmpIgnoreCase;
 use std::borrow::Cow;
 use std::cmp::Ordering;
 
 use super::xmlns::Xmlns;
+use xml::namespace::{NS_XML_PREFIX, NS_XML_URI};
+
+/// Namespace of the `wsu:Id` attribute (WS-Security utility)
+pub(crate) const NS_WSU_URI: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd";
 
 /// Represents a XML attribute by xml namespace, local name and string value
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub(crate) struct XmlAttr {
     pub ns: Option<Xmlns>,
     pub local_name: String,
@@ -13,31 +17,67 @@ pub(crate) struct XmlAttr {
 }
 
 impl XmlAttr {
-    pub fn get_fullname(&self) -> Cow<str> {
+    pub fn get_fullname(&self) -> Cow<'_, str> {
         match &self.ns {
             Some(ns) => Cow::Owned(format!("{}:{}", ns.prefix, self.local_name)),
             None => Cow::Borrowed(&self.local_name),
         }
     }
+
+    /// Returns the namespace URI of the attribute, or an empty string for unqualified attributes
+    pub fn get_ns_url(&self) -> &str {
+        match &self.ns {
+            Some(ns) => &ns.url,
+            None => "",
+        }
+    }
+
+    /// Creates an attribute in the `xml` namespace, e.g. `xml:lang`
+    pub fn new_xml_attr(local_name: &str, value: String) -> Self {
+        Self {
+            ns: Some(Xmlns::new_xml()),
+            local_name: local_name.to_owned(),
+            value,
+        }
+    }
+
+    /// Returns `true` if this is an attribute in the `xml` namespace (`xml:lang`, `xml:base`, ...)
+    pub fn is_xml_attr(&self) -> bool {
+        self.ns
+            .as_ref()
+            .is_some_and(|ns| ns.prefix.as_str() == NS_XML_PREFIX)
+    }
+
+    /// Returns `true` for the attributes that identify an element in a same-document reference
+    /// (`URI="#id"`): `wsu:Id`, `xml:id` and the unqualified `Id`
+    pub fn is_id_attr(&self) -> bool {
+        matches!(
+            (self.get_ns_url(), self.local_name.as_str()),
+            ("" | NS_WSU_URI, "Id") | (NS_XML_URI, "id")
+        )
+    }
 }
 
-/// Atributy se řadí podle abecedy podle celého názvu
+/// Atributy se řadí podle namespace URI a pak podle lokálního jména (C14N 1.0, section 2.2).
+/// Strings are compared by Unicode code points. Unqualified attributes have an empty namespace URI,
+/// so they come first.
 impl PartialOrd for XmlAttr {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-/// Atributy se řadí podle abecedy podle celého názvu
+/// Atributy se řadí podle namespace URI a pak podle lokálního jména (C14N 1.0, section 2.2).
+/// Strings are compared by Unicode code points. Unqualified attributes have an empty namespace URI,
+/// so they come first.
 impl Ord for XmlAttr {
     fn cmp(&self, other: &Self) -> Ordering {
-        match (self.ns.clone(), other.ns.clone()) {
-            (None, None) => self.local_name.cmp(&other.local_name),
-            (None, Some(_)) => Ordering::Less, // prvne se renderuji bezprefixove atributy
-            (Some(_), None) => Ordering::Greater, // prvne se renderuji bezprefixove atributy
-            (Some(self_ns), Some(other_ns)) => self_ns
-                .cmp(&other_ns)
-                .then(self.local_name.cmp_ignore_case(&other.local_name)),
-        }
+        // Porovnani UTF-8 bajtu odpovida porovnani podle code pointu, jak vyzaduje C14N
+        self.get_ns_url()
+            .cmp(other.get_ns_url())
+            .then_with(|| self.local_name.cmp(&other.local_name))
+            // Pro konzistenci s Eq; v platnem XML se atributy se stejnym jmenem nevyskytuji
+            .then_with(|| self.ns.cmp(&other.ns))
+            .then_with(|| self.value.cmp(&other.value))
     }
 }
@@ -1,26 +1,65 @@
 use std::{
     borrow::Cow,
-    cell::{Ref, RefCell, RefMut},
     collections::BTreeSet,
-    rc::Rc,
+    fmt::{self, Write},
+    ops::Index,
 };
+use xml::common::TextPosition;
 
-use super::{xml_attr::XmlAttr, xmlns::Xmlns, xmlns_collection::XmlnsCollection};
+use super::{
+    canonicalization::{C14nAlgorithm, C14nSettings},
+    escape::{escape_attr_value, escape_text},
+    inherited_xml_attrs::InheritedXmlAttrs,
+    xml_attr::XmlAttr,
+    xmlns::Xmlns,
+    xmlns_scope::XmlnsScope,
+};
 
-#[derive(Debug, Clone)]
-pub(crate) struct XmlElemRc(Rc<RefCell<XmlElem>>);
+/// Namespace of XML digital signatures (`ds:Signature`, ...)
+pub(crate) const NS_DS_URI: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+/// Index of an element in the `XmlElemArena` of its document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct ElemId(usize);
 
-impl XmlElemRc {
-    pub fn new(x: XmlElem) -> Self {
-        Self(Rc::new(RefCell::new(x)))
+/// All elements of a document in one `Vec`, in document order.
+/// Elements refer to their children by `ElemId`, so the tree has no shared ownership
+/// or runtime borrow checks and can be sent between threads.
+#[derive(Debug, Default)]
+pub(crate) struct XmlElemArena(Vec<XmlElem>);
+
+impl XmlElemArena {
+    /// Adds the element and returns its id
+    pub fn push(&mut self, elem: XmlElem) -> ElemId {
+        self.0.push(elem);
+        ElemId(self.0.len() - 1)
     }
 
-    pub fn borrow_elem(&self) -> Ref<XmlElem> {
-        self.0.borrow()
+    pub fn get_mut(&mut self, id: ElemId) -> &mut XmlElem {
+        &mut self.0[id.0]
     }
+}
 
-    pub fn borrow_elem_mut(&self) -> RefMut<XmlElem> {
-        self.0.borrow_mut()
+impl Index<ElemId> for XmlElemArena {
+    type Output = XmlElem;
+
+    fn index(&self, id: ElemId) -> &XmlElem {
+        &self.0[id.0]
+    }
+}
+
+/// Elements left out of the canonicalized node set, together with their subtrees.
+/// E.g. the `ds:Signature` removed by the enveloped-signature transform.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExcludedElems(BTreeSet<ElemId>);
+
+impl ExcludedElems {
+    pub fn new(elems: impl IntoIterator<Item = ElemId>) -> Self {
+        Self(elems.into_iter().collect())
+    }
+
+    pub fn contains(&self, elem: ElemId) -> bool {
+        self.0.contains(&elem)
     }
 }
 
@@ -31,133 +70,347 @@ pub(crate) struct XmlElem {
 
     pub local_name: String,
 
-    /// Content je buď množina XML elementů, nebo text (může být prázdný)
-    pub content: ElemContent,
+    /// Obsah elementu: elementy, texty a komentáře v pořadí, v jakém jsou v dokumentu
+    pub children: Vec<XmlNode>,
+
+    /// Rodic elementu, koren dokumentu ho nema
+    pub parent: Option<ElemId>,
 
-    /// Namespacy, které element zná. Buďto je sám definuje, nebo je zná po předcích
-    pub known_nss: XmlnsCollection,
+    /// Pozice start tagu ve vstupu, pro hlaseni chyb
+    pub position: TextPosition,
+
+    /// Namespacy, ktere element sam deklaruje nebo kterym meni url.
+    /// Namespacy zdedene od predku se skladaji az pri pruchodu stromem v `XmlnsScope`.
+    pub declared_nss: Vec<Xmlns>,
 
     /// Namespacy, které tento element přímo používá ve svých atributech či názvu.
     pub used_nss: BTreeSet<Xmlns>,
 
     /// XML atributy elementu
     pub attrs: BTreeSet<XmlAttr>,
+
+    /// `xml:*` attributes inherited from the ancestors (not including the element's own ones)
+    pub inherited_xml_attrs: InheritedXmlAttrs,
 }
 
 impl XmlElem {
-    /// Přidá child element. Pokud byl dříve obsahem elementu prostý text, bude zahozen.
-    pub fn add_child(&mut self, child: XmlElemRc) {
-        match &mut self.content {
-            ElemContent::Children(children) => children.push(child),
-            _ => self.content = ElemContent::Children(vec![child]),
-        }
+    /// Přidá child uzel za dosavadní obsah elementu.
+    pub fn add_child(&mut self, child: XmlNode) {
+        self.children.push(child);
     }
 
-    /// Dá XML elementu textovou hodnotu. Pokud měl element dříve child elementy, budou zahozeny.
-    pub fn set_text_value(&mut self, val: String) {
-        match val.as_str() {
-            _ => self.content = ElemContent::Text(val),
-        };
+    /// Přidá text za dosavadní obsah elementu.
+    /// Navazuje-li na jiný text, spojí se s ním do jednoho textového uzlu.
+    pub fn add_text(&mut self, val: String) {
+        match self.children.last_mut() {
+            Some(XmlNode::Text(text)) => text.push_str(&val),
+            _ => self.children.push(XmlNode::Text(val)),
+        }
     }
 
-    pub fn get_fullname(&self) -> Cow<str> {
+    pub fn get_fullname(&self) -> Cow<'_, str> {
         match &self.ns {
             Some(ns) => Cow::Owned(format!("{}:{}", ns.prefix, self.local_name)),
             None => Cow::Borrowed(&self.local_name),
         }
     }
 
+    /// Returns the namespace URI of the element (of its prefix, or the default namespace in scope).
+    /// Returns an empty string if the element is in no namespace.
+    pub fn get_ns_url(&self) -> &str {
+        match &self.ns {
+            Some(ns) => &ns.url,
+            None => self
+                .used_nss
+                .iter()
+                .find(|ns| ns.is_default())
+                .map_or("", |ns| &*ns.url),
+        }
+    }
+
+    /// Returns `true` for the `ds:Signature` element of XML digital signatures
+    pub fn is_signature(&self) -> bool {
+        self.local_name == "Signature" && self.get_ns_url() == NS_DS_URI
+    }
+
+    /// Returns `true` if the element has a `wsu:Id`, `xml:id` or `Id` attribute with the given value
+    pub fn has_id(&self, id: &str) -> bool {
+        self.attrs.iter().any(|a| a.is_id_attr() && a.value == id)
+    }
+
+    /// Returns the element's own `xml:*` attribute with the given local name
+    fn get_own_xml_attr(&self, local_name: &str) -> Option<&XmlAttr> {
+        self.attrs
+            .iter()
+            .find(|a| a.is_xml_attr() && a.local_name == local_name)
+    }
+
+    /// Returns the `xml:*` attributes to write on the apex element of the canonicalized subtree.
+    /// They replace the element's own attributes of the same name.
+    /// - Exclusive canonicalization doesn't inherit anything.
+    /// - Canonical XML 1.0 inherits the nearest value of every `xml:*` attribute.
+    /// - Canonical XML 1.1 inherits `xml:lang`, `xml:space` and other simple inheritable
+    ///   attributes, but not `xml:id`. `xml:base` is joined with the values of all ancestors.
+    fn get_apex_xml_attrs(&self, algorithm: C14nAlgorithm) -> Vec<XmlAttr> {
+        let inherited = self
+            .inherited_xml_attrs
+            .iter_nearest()
+            .filter(|(name, _)| self.get_own_xml_attr(name).is_none());
+
+        match algorithm {
+            C14nAlgorithm::Exclusive | C14nAlgorithm::ExclusiveWithComments => Vec::new(),
+            C14nAlgorithm::Inclusive | C14nAlgorithm::InclusiveWithComments => inherited
+                .map(|(name, value)| XmlAttr::new_xml_attr(name, value.to_owned()))
+                .collect(),
+            C14nAlgorithm::Inclusive11 | C14nAlgorithm::Inclusive11WithComments => {
+                let mut attrs: Vec<XmlAttr> = inherited
+                    .filter(|(name, _)| !matches!(*name, "id" | "base"))
+                    .map(|(name, value)| XmlAttr::new_xml_attr(name, value.to_owned()))
+                    .collect();
+                let own_base = self.get_own_xml_attr("base").map(|a| a.value.as_str());
+                if let Some(base) = self.inherited_xml_attrs.joined_base(own_base) {
+                    attrs.push(XmlAttr::new_xml_attr("base", base));
+                }
+                attrs
+            }
+        }
+    }
+
     /// Použito pro renderování finálního kanonizovaného XML.
-    /// Vrátí atributy seřazené podle celého názvu
-    pub fn get_ordered_attrs(&self) -> String {
+    /// Vrátí atributy seřazené podle celého názvu.
+    /// `apex_attrs` jsou zdedene `xml:*` atributy, ktere nahradi vlastni atributy stejneho jmena.
+    pub fn get_ordered_attrs(&self, apex_attrs: &[XmlAttr]) -> String {
+        let mut attrs: BTreeSet<&XmlAttr> = apex_attrs.iter().collect();
+        attrs.extend(self.attrs.iter().filter(|a| {
+            !(a.is_xml_attr() && apex_attrs.iter().any(|x| x.local_name == a.local_name))
+        }));
+
         let mut attrs_str = String::new();
-        for a in self.attrs.iter() {
-            attrs_str += &format!(" {}=\"{}\"", a.get_fullname(), escape_xml(&a.value));
+        for a in attrs {
+            attrs_str += &format!(" {}=\"{}\"", a.get_fullname(), escape_attr_value(&a.value));
         }
         attrs_str
     }
 
     /// Použito pro renderování finálního kanonizovaného XML.
-    /// Vrátí inner xml jako string
-    pub fn content_as_string(&self, written_xmlns: BTreeSet<Xmlns>) -> Cow<str> {
-        match &self.content {
-            ElemContent::Text(txt) => Cow::Borrowed(txt),
-            ElemContent::Children(elems) => {
-                Cow::Owned(XmlElem::many_nodes_as_xml_string(&elems, written_xmlns))
-            }
+    /// Proiteruje obsah elementu a zapíše jeho XML textovou reprezentaci
+    fn write_content<W: Write>(
+        &self,
+        arena: &XmlElemArena,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+        scopes: &mut NsScopes,
+        out: &mut W,
+    ) -> fmt::Result {
+        for n in self.children.iter() {
+            n.write_canonized(arena, settings, excluded, scopes, out)?;
         }
+        Ok(())
     }
 
-    /// Použito pro renderování finálního kanonizovaného XML.
-    /// Proiteruje pole XML elementů, přičemž z nich vyrobí XML textovou reprezentaci
-    fn many_nodes_as_xml_string(elems: &[XmlElemRc], written_xmlns: BTreeSet<Xmlns>) -> String {
-        if elems.len() == 1 {
-            elems[0]
-                .borrow_elem()
-                .get_canonized_xml_recur(written_xmlns)
-        } else {
-            let mut s = String::new();
-            for e in elems {
-                s += &e
-                    .borrow_elem()
-                    .get_canonized_xml_recur(written_xmlns.clone());
-            }
-            s
-        }
+    /// Returns the namespaces this element has to declare in the canonical output.
+    /// Exclusive canonicalization only considers the visibly used namespaces
+    /// and the namespaces from the `InclusiveNamespaces PrefixList`,
+    /// inclusive canonicalization considers every namespace in scope.
+    /// Below the apex, every namespace in scope which the element doesn't declare itself
+    /// has already been considered by its parent, so only the own declarations are checked.
+    fn get_xmlns_to_declare(
+        &self,
+        settings: &C14nSettings,
+        scopes: &NsScopes,
+        is_apex: bool,
+    ) -> BTreeSet<Xmlns> {
+        let candidates: Box<dyn Iterator<Item = &Xmlns>> = match settings.algorithm {
+            C14nAlgorithm::Exclusive | C14nAlgorithm::ExclusiveWithComments => Box::new(
+                self.used_nss.iter().chain(
+                    settings
+                        .inclusive_prefixes
+                        .iter()
+                        .filter_map(|prefix| scopes.known.find_by_prefix(prefix)),
+                ),
+            ),
+            C14nAlgorithm::Inclusive
+            | C14nAlgorithm::InclusiveWithComments
+            | C14nAlgorithm::Inclusive11
+            | C14nAlgorithm::Inclusive11WithComments => match is_apex {
+                true => Box::new(scopes.known.iter()),
+                false => Box::new(self.declared_nss.iter()),
+            },
+        };
+
+        candidates
+            .filter(|ns| !ns.is_reserved() && scopes.written.needs_declaration(ns))
+            .cloned()
+            .collect()
     }
 
-    pub(crate) fn get_canonized_xml_recur(&self, mut written_xmlns: BTreeSet<Xmlns>) -> String {
-        let mut local_written_xmlns = BTreeSet::<Xmlns>::new();
+    /// Writes the canonical form of this element as the apex of the canonicalized tree.
+    /// Inclusive algorithms also write the `xml:*` attributes inherited from the ancestors.
+    /// The children are looked up in `arena`, the excluded elements and their subtrees are left out.
+    pub(crate) fn write_canonized<W: Write>(
+        &self,
+        arena: &XmlElemArena,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+        out: &mut W,
+    ) -> fmt::Result {
+        let mut scopes = NsScopes::for_apex(self, arena);
+        self.open_known_scope(&mut scopes);
+        let apex_attrs = self.get_apex_xml_attrs(settings.algorithm);
+        out.write_str(&self.get_start_tag(settings, &mut scopes, Some(&apex_attrs)))?;
+        self.write_content(arena, settings, excluded, &mut scopes, out)?;
+        out.write_str(&self.get_end_tag(&mut scopes))
+    }
 
-        for used_ns in self.used_nss.iter() {
-            if !written_xmlns.contains(used_ns) {
-                local_written_xmlns.insert(used_ns.clone());
-            }
+    fn write_canonized_recur<W: Write>(
+        &self,
+        arena: &XmlElemArena,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+        scopes: &mut NsScopes,
+        out: &mut W,
+    ) -> fmt::Result {
+        self.open_known_scope(scopes);
+        out.write_str(&self.get_start_tag(settings, scopes, None))?;
+        self.write_content(arena, settings, excluded, scopes, out)?;
+        out.write_str(&self.get_end_tag(scopes))
+    }
+
+    /// Opens the scope of the element in the input namespaces and declares its own namespaces
+    fn open_known_scope(&self, scopes: &mut NsScopes) {
+        scopes.known.push_scope();
+        for ns in self.declared_nss.iter() {
+            scopes.known.declare(ns.clone());
         }
+    }
 
+    /// Returns the canonical start tag with the namespace declarations and attributes.
+    /// The scope of the element in the input namespaces (`scopes.known`) has to be open already,
+    /// the scope in the output namespaces is opened here. `get_end_tag` closes both.
+    /// `apex_attrs` are given for the apex of the canonicalized tree only, they are the
+    /// inherited `xml:*` attributes which replace the element's own attributes of the same name.
+    pub(crate) fn get_start_tag(
+        &self,
+        settings: &C14nSettings,
+        scopes: &mut NsScopes,
+        apex_attrs: Option<&[XmlAttr]>,
+    ) -> String {
+        let to_declare = self.get_xmlns_to_declare(settings, scopes, apex_attrs.is_some());
+        scopes.written.push_scope();
         let mut local_written_xmlns_str = String::with_capacity(256);
-        for n in local_written_xmlns {
-            local_written_xmlns_str += &format!(
-                " {}=\"{}\"",
-                if n.is_default() {
-                    "xmlns".to_owned()
-                } else {
-                    format!("xmlns:{}", &n.prefix)
-                },
-                n.url
-            );
-            written_xmlns.insert(n);
+        for n in to_declare {
+            local_written_xmlns_str += &n.as_declaration();
+            scopes.written.declare(n);
         }
 
         format!(
-            "<{fullname}{nss}{attrs}>{content}</{fullname}>",
+            "<{fullname}{nss}{attrs}>",
             fullname = self.get_fullname(),
             nss = local_written_xmlns_str,
-            attrs = self.get_ordered_attrs(),
-            content = self.content_as_string(written_xmlns)
+            attrs = self.get_ordered_attrs(apex_attrs.unwrap_or_default()),
         )
     }
+
+    /// Returns the canonical end tag and closes the scope of the element in `scopes`
+    pub(crate) fn get_end_tag(&self, scopes: &mut NsScopes) -> String {
+        scopes.known.pop_scope();
+        scopes.written.pop_scope();
+        format!("</{}>", self.get_fullname())
+    }
 }
 
-#[derive(Debug)]
-pub(crate) enum ElemContent {
-    Text(String),
-    Children(Vec<XmlElemRc>),
+/// Namespaces in scope while rendering the canonical form
+#[derive(Debug, Default)]
+pub(crate) struct NsScopes {
+    /// Namespaces declared in the input document
+    pub known: XmlnsScope,
+
+    /// Namespaces already declared in the output
+    pub written: XmlnsScope,
 }
 
-fn escape_xml(inner_text: &str) -> String {
-    let mut escaped_text = String::with_capacity(inner_text.len());
-
-    for ch in inner_text.chars() {
-        match ch {
-            '&' => escaped_text.push_str("&amp;"),
-            '<' => escaped_text.push_str("&lt;"),
-            '>' => escaped_text.push_str("&gt;"),
-            '"' => escaped_text.push_str("&quot;"),
-            '\'' => escaped_text.push_str("&apos;"),
-            _ => escaped_text.push(ch),
+impl NsScopes {
+    /// Creates the scopes for rendering `apex`, with the namespaces declared by its ancestors.
+    /// Nothing is written yet, so the apex declares all namespaces it needs.
+    pub fn for_apex(apex: &XmlElem, arena: &XmlElemArena) -> Self {
+        let mut ancestors = Vec::new();
+        let mut parent = apex.parent;
+        while let Some(id) = parent {
+            ancestors.push(&arena[id]);
+            parent = arena[id].parent;
+        }
+
+        // Od korene dolu, blizsi predek prepisuje vzdalenejsiho
+        let mut scopes = Self::default();
+        for ancestor in ancestors.into_iter().rev() {
+            for ns in ancestor.declared_nss.iter() {
+                scopes.known.declare(ns.clone());
+            }
         }
+        scopes
     }
+}
+
+/// A child node of an element, or a node outside of the root element
+#[derive(Debug, Clone)]
+pub(crate) enum XmlNode {
+    Elem(ElemId),
 
-    escaped_text
+    /// Text content. Adjacent texts are merged into one node.
+    Text(String),
+
+    /// Text of the comment, without `<!--` and `-->`
+    Comment(String),
+
+    /// Processing instruction `<?target data?>`. `data` is empty if the PI has no data.
+    ProcessingInstruction {
+        target: String,
+        data: String,
+    },
+}
+
+impl XmlNode {
+    /// Returns the node in its canonical form, see `write_canonized`.
+    /// An empty string is returned if nothing is written.
+    pub fn get_canonized_xml(
+        &self,
+        arena: &XmlElemArena,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+        scopes: &mut NsScopes,
+    ) -> String {
+        let mut xml = String::new();
+        // Zapis do String nemuze selhat
+        let _ = self.write_canonized(arena, settings, excluded, scopes, &mut xml);
+        xml
+    }
+
+    /// Writes the node in its canonical form.
+    /// Comments are only written by the `#WithComments` algorithms.
+    /// Processing instructions are always written, excluded elements never.
+    /// `scopes` hold the namespaces in scope at the node.
+    pub fn write_canonized<W: Write>(
+        &self,
+        arena: &XmlElemArena,
+        settings: &C14nSettings,
+        excluded: &ExcludedElems,
+        scopes: &mut NsScopes,
+        out: &mut W,
+    ) -> fmt::Result {
+        match self {
+            XmlNode::Elem(id) if excluded.contains(*id) => Ok(()),
+            XmlNode::Elem(id) => {
+                arena[*id].write_canonized_recur(arena, settings, excluded, scopes, out)
+            }
+            XmlNode::Text(text) => out.write_str(&escape_text(text)),
+            XmlNode::Comment(text) if settings.algorithm.with_comments() => {
+                write!(out, "<!--{text}-->")
+            }
+            XmlNode::Comment(_) => Ok(()),
+            XmlNode::ProcessingInstruction { target, data } if data.is_empty() => {
+                write!(out, "<?{target}?>")
+            }
+            XmlNode::ProcessingInstruction { target, data } => write!(out, "<?{target} {data}?>"),
+        }
+    }
 }
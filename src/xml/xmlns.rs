@@ -1,41 +1,191 @@
-use crate::tools::StrCmpIgnoreCase;
-use std::rc::Rc;
+use super::escape::escape_attr_value;
+use crate::tools::str_to_tinystr16;
+use std::{
+    borrow::Borrow,
+    collections::HashSet,
+    fmt::{Debug, Display},
+    hash::Hash,
+    ops::Deref,
+    sync::Arc,
+};
+use tinystr::TinyStr16;
+use xml::namespace::{NS_XML_PREFIX, NS_XML_URI, NS_XMLNS_PREFIX};
 
 /// Represents a XML namespace (prefix and url)
 #[derive(Debug, Eq, Clone, derive_more::Constructor)]
 pub(crate) struct Xmlns {
-    /// The URL is wrapped in `Rc` to avoid unnecessary allocations on clone.
-    pub url: Rc<String>,
+    /// The URL is interned (see `NsInterner`), clones share one allocation.
+    pub url: Arc<str>,
 
-    /// XML namespace prefix assumes max. 16 ASCII chars
-    pub prefix: tinystr::TinyStr16,
+    pub prefix: XmlnsPrefix,
+}
+
+/// Namespace prefix, any NCName (empty for the default namespace).
+/// Prefixes of up to 16 ASCII characters, the usual case, are stored inline without allocation,
+/// longer or non-ASCII ones are interned (see `NsInterner`).
+/// Compares, orders and hashes as the string.
+#[derive(Clone)]
+pub(crate) enum XmlnsPrefix {
+    Short(TinyStr16),
+    Long(Arc<str>),
+}
+
+impl XmlnsPrefix {
+    pub fn new(prefix: &str, interner: &mut NsInterner) -> Self {
+        match str_to_tinystr16(prefix) {
+            Ok(short) => XmlnsPrefix::Short(short),
+            Err(_) => XmlnsPrefix::Long(interner.intern(prefix)),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            XmlnsPrefix::Short(short) => short.as_str(),
+            XmlnsPrefix::Long(long) => long,
+        }
+    }
+}
+
+impl Deref for XmlnsPrefix {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Allows lookups by `&str` in hash maps
+impl Borrow<str> for XmlnsPrefix {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for XmlnsPrefix {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (XmlnsPrefix::Short(a), XmlnsPrefix::Short(b)) => a == b,
+            _ => self.as_str() == other.as_str(),
+        }
+    }
+}
+
+impl Eq for XmlnsPrefix {}
+
+impl PartialOrd for XmlnsPrefix {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Porovnani UTF-8 bajtu odpovida porovnani podle code pointu, jak vyzaduje C14N
+impl Ord for XmlnsPrefix {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+/// Hashes as `str`, see `Borrow<str>`
+impl Hash for XmlnsPrefix {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl Display for XmlnsPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Debug for XmlnsPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
 }
 
 impl Xmlns {
-    /// Returns `true` if this is the default namespace (i.e., no prefix, `xmlns="..."`)
+    /// Returns the implicitly bound `xml` namespace
+    pub fn new_xml() -> Self {
+        Self::new(
+            Arc::from(NS_XML_URI),
+            XmlnsPrefix::Short(str_to_tinystr16(NS_XML_PREFIX).unwrap()),
+        )
+    }
+
+    /// Returns `true` if this is the default namespace (i.e., no prefix, `xmlns="..."`),
+    /// including its undeclaration `xmlns=""`
     pub fn is_default(&self) -> bool {
-        self.prefix.is_empty() && !self.url.is_empty()
+        self.prefix.is_empty()
+    }
+
+    /// Returns `true` for `xmlns=""`, which undeclares the default namespace.
+    /// Elements without a prefix are then in no namespace.
+    pub fn is_default_undeclaration(&self) -> bool {
+        self.is_default() && self.url.is_empty()
+    }
+
+    /// Returns `true` for the reserved `xml` and `xmlns` prefixes.
+    /// These are bound implicitly and never declared in the canonical output.
+    pub fn is_reserved(&self) -> bool {
+        matches!(self.prefix.as_str(), NS_XML_PREFIX | NS_XMLNS_PREFIX)
+    }
+
+    /// Returns the namespace declaration as an attribute, e.g. ` xmlns:a="http://a.a"`
+    pub fn as_declaration(&self) -> String {
+        if self.prefix.is_empty() {
+            format!(" xmlns=\"{}\"", escape_attr_value(&self.url))
+        } else {
+            format!(
+                " xmlns:{}=\"{}\"",
+                self.prefix,
+                escape_attr_value(&self.url)
+            )
+        }
     }
 }
 
 impl PartialEq for Xmlns {
     fn eq(&self, other: &Self) -> bool {
-        self.prefix == other.prefix && self.url == other.url
+        // Interned URLs of one document are mostly the same allocation
+        self.prefix == other.prefix && (Arc::ptr_eq(&self.url, &other.url) || self.url == other.url)
     }
 }
 
-/// Xmlns are ordered by prefix, then by Uri
+/// Xmlns are ordered by prefix, then by Uri.
+/// Strings are compared by Unicode code points, the default namespace (empty prefix) comes first.
 impl PartialOrd for Xmlns {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
-/// Xmlns are ordered by prefix, then by Uri
+/// Xmlns are ordered by prefix, then by Uri.
+/// Strings are compared by Unicode code points, the default namespace (empty prefix) comes first.
 impl Ord for Xmlns {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // Porovnani UTF-8 bajtu odpovida porovnani podle code pointu, jak vyzaduje C14N
         self.prefix
-            .cmp_ignore_case(&other.prefix)
-            .then(self.url.cmp_ignore_case(&other.url))
+            .cmp(&other.prefix)
+            .then_with(|| self.url.cmp(&other.url))
+    }
+}
+
+/// Keeps one shared copy of every namespace URL and long prefix of a document,
+/// so that the namespaces of all elements don't allocate the same strings again and again.
+#[derive(Debug, Default)]
+pub(crate) struct NsInterner(HashSet<Arc<str>>);
+
+impl NsInterner {
+    /// Returns the shared copy of the string
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        match self.0.get(s) {
+            Some(interned) => interned.clone(),
+            None => {
+                let interned: Arc<str> = Arc::from(s);
+                self.0.insert(interned.clone());
+                interned
+            }
+        }
     }
 }
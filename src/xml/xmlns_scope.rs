@@ -0,0 +1,67 @@
+use super::xmlns::{Xmlns, XmlnsPrefix};
+use std::collections::HashMap;
+
+/// Namespaces in scope while walking a document, looked up by prefix in a hash map.
+/// A scope is opened for every element and closed at its end,
+/// closing it restores the bindings the element replaced.
+/// Used both for the namespaces declared in the input and for those already written to the output.
+#[derive(Debug, Default)]
+pub(crate) struct XmlnsScope {
+    /// Current binding of every prefix in scope
+    bindings: HashMap<XmlnsPrefix, Xmlns>,
+
+    /// For every open scope: the prefixes bound in it and their previous bindings
+    undo: Vec<Vec<(XmlnsPrefix, Option<Xmlns>)>>,
+}
+
+impl XmlnsScope {
+    /// Opens the scope of an element
+    pub fn push_scope(&mut self) {
+        self.undo.push(Vec::new());
+    }
+
+    /// Closes the scope of an element and restores the bindings it replaced
+    pub fn pop_scope(&mut self) {
+        if let Some(undo) = self.undo.pop() {
+            for (prefix, previous) in undo.into_iter().rev() {
+                match previous {
+                    Some(previous) => self.bindings.insert(prefix, previous),
+                    None => self.bindings.remove(&prefix),
+                };
+            }
+        }
+    }
+
+    /// Binds the prefix of `xmlns` in the current scope.
+    /// Outside of any scope the binding is permanent.
+    pub fn declare(&mut self, xmlns: Xmlns) {
+        let prefix = xmlns.prefix.clone();
+        let previous = self.bindings.insert(prefix.clone(), xmlns);
+        if let Some(undo) = self.undo.last_mut() {
+            undo.push((prefix, previous));
+        }
+    }
+
+    /// Returns the namespace bound to the given prefix, if any
+    pub fn find_by_prefix(&self, prefix: &str) -> Option<&Xmlns> {
+        self.bindings.get(prefix)
+    }
+
+    /// Decides whether `xmlns` has to be declared on an element,
+    /// given that `self` holds the namespaces already declared by its output ancestors.
+    /// The undeclaration `xmlns=""` is only declared when an output ancestor declared
+    /// a non-empty default namespace, as both exclusive and inclusive canonicalization require.
+    /// Which ancestors count differs: in exclusive canonicalization only those which
+    /// visibly use the default namespace (or have it in the `PrefixList`) declare it.
+    pub fn needs_declaration(&self, xmlns: &Xmlns) -> bool {
+        match self.bindings.get(&xmlns.prefix) {
+            Some(found) => found.url != xmlns.url,
+            None => !xmlns.is_default_undeclaration(),
+        }
+    }
+
+    /// Iterates over all namespaces in scope, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Xmlns> {
+        self.bindings.values()
+    }
+}